@@ -0,0 +1,50 @@
+//! `encode_cow` on input that needs no escaping, which borrows it without
+//! allocating, against `encode`, which always builds a `String`
+//!
+//! Run with `cargo bench --bench encode_cow`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::borrow::Cow;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use jai::URLEncoder;
+
+/// Counts allocations so the no-op case can be shown to make none
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn encode_cow(c: &mut Criterion) {
+    let encoder = URLEncoder::new("a-long-readable-article-slug_v2.html".to_string());
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..1000 {
+        assert!(matches!(black_box(&encoder).encode_cow(), Cow::Borrowed(_)));
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert_eq!(allocations, 0, "encode_cow allocated on input that needs no escaping");
+
+    let mut group = c.benchmark_group("already safe slug");
+    group.bench_function("encode_cow", |b| b.iter(|| black_box(&encoder).encode_cow()));
+    group.bench_function("encode", |b| b.iter(|| black_box(&encoder).encode()));
+    group.finish();
+}
+
+criterion_group!(benches, encode_cow);
+criterion_main!(benches);
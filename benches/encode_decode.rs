@@ -0,0 +1,77 @@
+//! Table-driven encode and decode against the `format!` and
+//! `from_str_radix` loops they replaced, on 10 MB of ASCII
+//!
+//! Run with `cargo bench --bench encode_decode`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use jai::{EncodeMode, URLEncoder};
+
+/// The `format!`-per-byte encoder `encode` started as
+fn format_encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b' ' => "+".to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The `from_str_radix` decoder `decode` started as
+fn from_str_radix_decode(input: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        match &input[i..i + 1] {
+            "+" => bytes.push(b' '),
+            "%" => {
+                bytes.push(u8::from_str_radix(&input[i + 1..i + 3], 16).unwrap());
+                i += 2;
+            }
+            c => bytes.extend_from_slice(c.as_bytes()),
+        }
+        i += 1;
+    }
+    bytes
+}
+
+/// Deterministic pseudo-random ASCII, weighted towards characters that need escaping
+fn ascii_corpus(len: usize, mut seed: u64) -> String {
+    (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            char::from((seed >> 33) as u8 % 128)
+        })
+        .collect()
+}
+
+fn encode_decode(c: &mut Criterion) {
+    let input = ascii_corpus(10 * 1024 * 1024, 7);
+    let encoder = URLEncoder::new(input.clone()).with_mode(EncodeMode::Form);
+    let encoded = encoder.encode();
+    assert_eq!(encoded, format_encode(&input));
+    let decoder = URLEncoder::new(encoded.clone()).with_mode(EncodeMode::Form);
+    assert_eq!(decoder.decode().unwrap().as_bytes(), from_str_radix_decode(&encoded));
+
+    let mut group = c.benchmark_group("encode 10 MB");
+    group.sample_size(10).throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("format!", |b| b.iter(|| format_encode(black_box(&input))));
+    group.bench_function("table", |b| b.iter(|| black_box(&encoder).encode()));
+    group.finish();
+
+    let mut group = c.benchmark_group("decode 10 MB");
+    group.sample_size(10).throughput(Throughput::Bytes(encoded.len() as u64));
+    group.bench_function("from_str_radix", |b| {
+        b.iter(|| from_str_radix_decode(black_box(&encoded)))
+    });
+    group.bench_function("table", |b| b.iter(|| black_box(&decoder).decode().unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, encode_decode);
+criterion_main!(benches);
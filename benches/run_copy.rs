@@ -0,0 +1,100 @@
+//! Run-copying `encode_url` and `decode` against byte-at-a-time loops, on
+//! 10 MB of mostly unchanged URL text and of mostly escaped Japanese text
+//!
+//! Run with `cargo bench --bench run_copy`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use jai::URLEncoder;
+
+/// Uppercase hex digits by value, for `%XX`
+const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+/// `encode_url` one byte at a time: keep URL characters and escapes,
+/// escape the rest
+fn bytewise_encode_url(input: &[u8]) -> String {
+    let is_url_char = |b: u8| b.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=".contains(&b);
+    let is_escape_at = |i: usize| {
+        input[i] == b'%'
+            && input.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+            && input.get(i + 2).is_some_and(u8::is_ascii_hexdigit)
+    };
+    let mut out = Vec::with_capacity(input.len() * 3);
+    let mut i = 0;
+
+    while i < input.len() {
+        if is_escape_at(i) {
+            out.extend_from_slice(&input[i..i + 3]);
+            i += 3;
+            continue;
+        }
+        let b = input[i];
+        if is_url_char(b) {
+            out.push(b);
+        } else {
+            out.extend_from_slice(&[b'%', HEX[usize::from(b >> 4)], HEX[usize::from(b & 0xF)]]);
+        }
+        i += 1;
+    }
+    String::from_utf8(out).unwrap()
+}
+
+/// `decode` one byte at a time, for input with no malformed escapes
+fn bytewise_decode(input: &[u8]) -> String {
+    let nibble = |b: u8| match b {
+        b'0'..=b'9' => b - b'0',
+        b'a'..=b'f' => b - b'a' + 10,
+        _ => b - b'A' + 10,
+    };
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+
+    while i < input.len() {
+        match input[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                out.push(nibble(input[i + 1]) << 4 | nibble(input[i + 2]));
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).unwrap()
+}
+
+fn run_copy(c: &mut Criterion) {
+    let corpora = [
+        ("mostly unchanged", "/articles/2024/a-long-readable-slug-for-a-page?ref=home page&"),
+        ("mostly escaped", "キーワード検索 \"日本語\" <テキスト> & データ"),
+    ];
+
+    for (name, line) in corpora {
+        let input = line.repeat(10_000_000 / line.len());
+        let encoder = URLEncoder::new(input.clone());
+        let encoded = encoder.encode_url();
+        assert_eq!(encoded, bytewise_encode_url(input.as_bytes()));
+        let decoder = URLEncoder::new(encoded.clone());
+        assert_eq!(decoder.decode().unwrap(), bytewise_decode(encoded.as_bytes()));
+
+        let mut group = c.benchmark_group(format!("encode {}", name));
+        group.sample_size(10).throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_function("run copy", |b| b.iter(|| black_box(&encoder).encode_url()));
+        group.bench_function("byte at a time", |b| {
+            b.iter(|| bytewise_encode_url(black_box(input.as_bytes())))
+        });
+        group.finish();
+
+        let mut group = c.benchmark_group(format!("decode {}", name));
+        group.sample_size(10).throughput(Throughput::Bytes(encoded.len() as u64));
+        group.bench_function("run copy", |b| b.iter(|| black_box(&decoder).decode().unwrap()));
+        group.bench_function("byte at a time", |b| {
+            b.iter(|| bytewise_decode(black_box(encoded.as_bytes())))
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, run_copy);
+criterion_main!(benches);
@@ -0,0 +1,7 @@
+//! The `jai` C bindings as a `staticlib` and `cdylib`
+//!
+//! The functions live in `jai::ffi` and are declared in `include/jai.h`;
+//! this crate only gives them a C-linkable crate type, so `jai` itself
+//! stays an `rlib` that also builds without `std`.
+
+pub use jai::ffi::*;
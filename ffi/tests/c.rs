@@ -0,0 +1,38 @@
+//! Compile `tests/ffi/roundtrip.c` against `include/jai.h` and the static
+//! library, then run it
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The directory cargo writes `libjai_ffi.a` to: the parent of this test
+/// binary's `deps/`
+fn library_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().and_then(Path::parent).unwrap().to_path_buf()
+}
+
+#[test]
+fn c_program_round_trips_and_checks_error_codes() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("roundtrip");
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+
+    let status = Command::new(compiler)
+        .arg(root.join("tests/ffi/roundtrip.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(library_dir().join("libjai_ffi.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .expect("a C compiler (set CC to pick one)");
+    assert!(status.success(), "compiling roundtrip.c failed");
+
+    let output = Command::new(&program).output().unwrap();
+    assert!(
+        output.status.success(),
+        "roundtrip.c failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"ok\n");
+}
@@ -0,0 +1,63 @@
+/*
+ * C bindings for the jai URL library; link against the jai-ffi crate
+ * (libjai_ffi.a or libjai_ffi.so, built by `cargo build -p jai-ffi`).
+ *
+ * Strings are NUL-terminated UTF-8. Every char * a jai_* function returns
+ * belongs to the caller and must be released with jai_free_string, never
+ * free(). Inputs stay owned by the caller and are only read.
+ *
+ * On failure a function returns NULL and stores a JAI_ERROR_* code in
+ * *error; on success it stores JAI_OK. error may be NULL.
+ *
+ * Keep in sync with src/ffi.rs.
+ */
+
+#ifndef JAI_H
+#define JAI_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Success */
+#define JAI_OK 0
+/* The input pointer was NULL */
+#define JAI_ERROR_NULL_INPUT 1
+/* The input was not valid UTF-8 */
+#define JAI_ERROR_INPUT_NOT_UTF8 2
+/* The result holds a NUL byte (%00) and cannot be a C string */
+#define JAI_ERROR_NUL_IN_OUTPUT 3
+
+/* The DecodeError variants */
+#define JAI_ERROR_TRUNCATED_ESCAPE 10
+#define JAI_ERROR_INVALID_HEX 11
+#define JAI_ERROR_INVALID_UTF8 12
+#define JAI_ERROR_INVALID_BASE64 13
+#define JAI_ERROR_INVALID_PADDING 14
+#define JAI_ERROR_TRUNCATED_BASE64 15
+#define JAI_ERROR_ODD_HEX_LENGTH 16
+
+/* Percent-encode input, as `url_encoder <input> encode` does */
+char *jai_encode(const char *input, int *error);
+
+/*
+ * Percent-decode input. With lenient nonzero, a % not followed by two hex
+ * digits passes through unchanged, as with `decode --lenient`. On a
+ * decoding error, *error_offset gets the byte offset the error points at;
+ * error_offset may be NULL.
+ */
+char *jai_decode(const char *input, int lenient, int *error, size_t *error_offset);
+
+/* The analysis of input as the JSON object `analyze --json` prints */
+char *jai_analyze_json(const char *input, int *error);
+
+/* Release a string returned by a jai_* function; NULL is ignored */
+void jai_free_string(char *text);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* JAI_H */
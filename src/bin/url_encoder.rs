@@ -0,0 +1,5525 @@
+//! The url_encoder command line: argument parsing, help, batch input,
+//! output formats and the repl, on top of the `jai` library
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use jai::{
+    codec_with, decode_stream, dedupe_key, encode_stream, expand_template, extract_urls,
+    file_url_to_path, hexdump, html_report, parse_flat_json_object, parse_query_pairs,
+    parse_template_vars_json, path_to_file_url, recase_escapes, similarity, AggregateReport,
+    Anonymizer, Base64Config, CacheKeyHash, CacheKeyOptions, Codec, CodecOptions, Component,
+    ComponentOptions, DataUrl, DecodeOptions, Diff, EncodeMode, EncodeOptions, EncodeSet,
+    EquivOptions, FormEncoder, HexCase, HexOptions, LengthLimits, MergePolicy, Mismatch,
+    NormalizeOptions, ParseError, QueryBuilder, QueryEdit, QueryMap, Redactor, ReferenceKind,
+    ReportOptions, RewriteRule, SortKey, SortOrder, StreamError, StreamOptions, SuffixList,
+    TemplateValue, URLAnalysis, URLComponents, URLEncoder, UrlPart, Weights, ANALYZE_CSV_HEADER,
+    CODECS,
+};
+use style::{ColorChoice, Styler};
+
+/// Flags that take a value, so the value is never mistaken for a positional
+const VALUE_FLAGS: &[&str] = &[
+    "--component",
+    "--safe",
+    "--unsafe",
+    "--max-depth",
+    "--file",
+    "--batch",
+    "--jobs",
+    "--set",
+    "--append",
+    "--remove",
+    "--keep",
+    "--extra",
+    "--redact-param",
+    "--from-json",
+    "--merge-query",
+    "--on-conflict",
+    "--suffix-list",
+    "--entropy-threshold",
+    "--max-length",
+    "--max-host-length",
+    "--max-label-length",
+    "--max-value-length",
+    "--group-by",
+    "--by",
+    "--format",
+    "--output",
+    "--append-output",
+    "--color",
+    "--prefix",
+    "--separator",
+    "--codec",
+    "--op",
+    "--iterations",
+    "--vars-json",
+    "--scheme",
+    "--userinfo",
+    "--host",
+    "--port",
+    "--path",
+    "--query",
+    "--fragment",
+    "--hash",
+    "--key",
+    "--keep-params",
+    "--path-depth",
+];
+
+/// Flags every command takes
+const GLOBAL_FLAGS: &[&str] = &["--help", "--version", "--quiet", "--verbose", "--color"];
+
+/// Flags that say where operations read and write, listed in every
+/// operation's help
+const INPUT_OUTPUT_FLAGS: &[&str] =
+    &["--file", "--batch", "--jobs", "--raw", "--null", "--mmap", "--output", "--append-output"];
+
+/// Flags of the operations, besides `GLOBAL_FLAGS`
+const OPERATION_FLAGS: &[&str] = &[
+    "--append",
+    "--append-output",
+    "--batch",
+    "--by",
+    "--bytes",
+    "--chars",
+    "--codec",
+    "--component",
+    "--csv",
+    "--decode-dots",
+    "--display-unicode",
+    "--entropy-threshold",
+    "--extra",
+    "--fail-on-limit",
+    "--fail-on-risk",
+    "--file",
+    "--form",
+    "--format",
+    "--fragment",
+    "--group",
+    "--group-by",
+    "--hash",
+    "--hash-host",
+    "--hexdump",
+    "--host",
+    "--idna",
+    "--jobs",
+    "--json",
+    "--keep",
+    "--keep-default-port",
+    "--keep-dot-segments",
+    "--keep-escapes",
+    "--keep-fragment",
+    "--keep-param-order",
+    "--keep-params",
+    "--key",
+    "--lenient",
+    "--lossy",
+    "--lower-hex",
+    "--max-depth",
+    "--max-host-length",
+    "--max-label-length",
+    "--max-length",
+    "--max-value-length",
+    "--merge-query",
+    "--mmap",
+    "--no-double-encode",
+    "--no-header",
+    "--no-pad",
+    "--normalize-escapes",
+    "--null",
+    "--offsets",
+    "--on-conflict",
+    "--output",
+    "--path",
+    "--path-depth",
+    "--per-url",
+    "--port",
+    "--prefix",
+    "--query",
+    "--raw",
+    "--recursive",
+    "--redact",
+    "--redact-param",
+    "--remove",
+    "--reverse",
+    "--rfc3986",
+    "--safe",
+    "--scheme",
+    "--separator",
+    "--set",
+    "--show-dupes",
+    "--sort-params",
+    "--strip-fragment",
+    "--suffix-list",
+    "--summary",
+    "--unsafe",
+    "--upper",
+    "--url",
+    "--url-safe",
+    "--userinfo",
+];
+
+/// Commands other than the operations, in `COMMAND_HELP` order, each
+/// with the flags it takes besides `GLOBAL_FLAGS`
+const SUBCOMMANDS: &[(&str, &[&str])] = &[
+    ("query-build", &["--from-json"]),
+    ("form", &[]),
+    ("formbody", &["--json"]),
+    ("equiv", &["--ignore-fragment", "--ignore-param-order"]),
+    ("diff", &["--normalize"]),
+    ("similar", &["--batch", "--normalize"]),
+    ("resolve", &[]),
+    ("join", &["--before-query"]),
+    ("expand", &["--vars-json", "--strict"]),
+    ("bench", &["--file", "--op", "--iterations", "--json"]),
+    ("repl", &[]),
+    ("completions", &[]),
+];
+
+/// Shells `completions` writes a script for
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// FlagHelp documents one flag for the generated `--help`
+struct FlagHelp {
+    name: &'static str,
+    /// The short form, as in `-o`
+    short: Option<&'static str>,
+    /// Placeholder for the value of a value flag
+    value: Option<&'static str>,
+    help: &'static str,
+    /// What applies when the flag is not given
+    default: Option<&'static str>,
+    /// The one command this entry is for, when the flag means something
+    /// else there
+    command: Option<&'static str>,
+    /// Every value the flag takes, for shell completion
+    choices: &'static [&'static str],
+}
+
+impl FlagHelp {
+    const fn switch(name: &'static str, help: &'static str) -> FlagHelp {
+        FlagHelp {
+            name,
+            short: None,
+            value: None,
+            help,
+            default: None,
+            command: None,
+            choices: &[],
+        }
+    }
+
+    const fn value(name: &'static str, value: &'static str, help: &'static str) -> FlagHelp {
+        FlagHelp { value: Some(value), ..FlagHelp::switch(name, help) }
+    }
+
+    const fn short(self, short: &'static str) -> FlagHelp {
+        FlagHelp { short: Some(short), ..self }
+    }
+
+    const fn default(self, default: &'static str) -> FlagHelp {
+        FlagHelp { default: Some(default), ..self }
+    }
+
+    const fn only_for(self, command: &'static str) -> FlagHelp {
+        FlagHelp { command: Some(command), ..self }
+    }
+
+    const fn choices(self, choices: &'static [&'static str]) -> FlagHelp {
+        FlagHelp { choices, ..self }
+    }
+
+    /// The short and long forms, each once
+    fn forms(&self) -> Vec<&'static str> {
+        self.short.into_iter().chain(Some(self.name)).collect()
+    }
+
+    /// The flag as typed, as in `-o, --output <path>`
+    fn synopsis(&self) -> String {
+        let short = self.short.map_or("    ".to_string(), |short| format!("{}, ", short));
+        match self.value {
+            Some(value) => format!("{}{} {}", short, self.name, value),
+            None => format!("{}{}", short, self.name),
+        }
+    }
+}
+
+/// What `--help` says about each flag
+static FLAG_HELP: &[FlagHelp] = &[
+    FlagHelp::switch("--help", "Print help, for a command if one is named, and exit").short("-h"),
+    FlagHelp::switch("--version", "Print the version and the enabled features").short("-V"),
+    FlagHelp::switch("--quiet", "Print no results; only the exit status tells").short("-q"),
+    FlagHelp::switch("--verbose", "Trace each step to stderr; -vv for more detail").short("-v"),
+    FlagHelp::value("--color", "<when>", "Color output: auto, always or never")
+        .default("auto")
+        .choices(&["auto", "always", "never"]),
+    FlagHelp::value("--file", "<path>", "Read the input from a file"),
+    FlagHelp::value("--batch", "<path>", "Run on every line of a file, one result per line"),
+    FlagHelp::value("--jobs", "<n>", "Process batch lines on this many threads").default("1"),
+    FlagHelp::switch("--raw", "Take stdin as one input, newlines and all"),
+    FlagHelp::switch("--null", "Batch records end in NUL instead of a newline").short("-0"),
+    FlagHelp::switch("--mmap", "Map the --batch file into memory instead of reading it"),
+    FlagHelp::value("--output", "<path>", "Write results to a file, replaced once complete")
+        .short("-o"),
+    FlagHelp::value("--append-output", "<path>", "Add results to the end of a file"),
+    FlagHelp::switch("--form", "Form encoding: space is +, a literal + is %2B"),
+    FlagHelp::switch("--rfc3986", "RFC 3986 encoding: space is %20, + is kept"),
+    FlagHelp::value(
+        "--component",
+        "<name>",
+        "Encode for one component: path, segment, query, fragment or userinfo",
+    )
+    .choices(&["path", "segment", "path-segment", "query", "fragment", "userinfo"]),
+    FlagHelp::value(
+        "--safe",
+        "<chars>",
+        "Also leave these unescaped (% never, + only with --rfc3986)",
+    ),
+    FlagHelp::value("--unsafe", "<chars>", "Also escape these characters"),
+    FlagHelp::switch("--no-double-encode", "Keep existing %XX escapes as they are"),
+    FlagHelp::switch("--url", "Encode a whole URL, escaping only what never belongs in one"),
+    FlagHelp::switch("--lower-hex", "Write escapes with lowercase hex digits, as in %2f"),
+    FlagHelp::switch("--idna", "Convert the host to xn-- form and percent-encode the rest"),
+    FlagHelp::value(
+        "--codec",
+        "<name>",
+        "Use another codec: percent, form, base64, base64url, hex or html",
+    )
+    .default("percent")
+    .choices(CODECS),
+    FlagHelp::switch("--lenient", "Pass a % that starts no valid escape through unchanged"),
+    FlagHelp::switch("--lossy", "Replace what does not decode with U+FFFD instead of failing"),
+    FlagHelp::switch("--bytes", "Write the decoded bytes as they are, UTF-8 or not"),
+    FlagHelp::switch("--hexdump", "Show the decoded bytes as a hex dump"),
+    FlagHelp::switch("--recursive", "Decode again until the output stops changing"),
+    FlagHelp::value("--max-depth", "<n>", "Most passes --recursive makes").default("5"),
+    FlagHelp::switch("--json", "Shorthand for --format json"),
+    FlagHelp::switch("--csv", "Shorthand for --format csv"),
+    FlagHelp::value("--format", "<name>", "text, json, csv, markdown, html or yaml")
+        .default("text")
+        .choices(&["text", "json", "csv", "markdown", "html", "yaml"]),
+    FlagHelp::switch("--no-header", "Leave out the CSV header row"),
+    FlagHelp::switch("--summary", "End a batch with aggregate totals"),
+    FlagHelp::switch("--per-url", "With --summary or --group-by, still print each result"),
+    FlagHelp::value("--group-by", "<key>", "Group batch totals by registrable domain")
+        .choices(&["domain"]),
+    FlagHelp::switch("--redact", "Mask passwords and secret-looking query values"),
+    FlagHelp::value("--redact-param", "<key>", "Also mask this query parameter; repeatable"),
+    FlagHelp::switch("--chars", "Add a character histogram"),
+    FlagHelp::value("--entropy-threshold", "<bits>", "Warn about hosts and queries above this")
+        .default("4.0"),
+    FlagHelp::switch("--fail-on-risk", "Exit with status 3 if a risk flag is raised"),
+    FlagHelp::value("--max-length", "<n>", "Longest URL before a warning").default("2083"),
+    FlagHelp::value("--max-host-length", "<n>", "Longest host").default("253"),
+    FlagHelp::value("--max-label-length", "<n>", "Longest host label").default("63"),
+    FlagHelp::value("--max-value-length", "<n>", "Longest query value").default("1024"),
+    FlagHelp::switch("--fail-on-limit", "Exit with status 3 if a length limit is exceeded"),
+    FlagHelp::switch("--display-unicode", "Show xn-- hosts in Unicode next to the ASCII"),
+    FlagHelp::value("--suffix-list", "<path>", "Public suffix list for registrable domains")
+        .default("built in"),
+    FlagHelp::switch("--group", "List repeated parameters under one key"),
+    FlagHelp::value("--set", "<k=v>", "Set a query parameter, replacing its values"),
+    FlagHelp::value("--append", "<k=v>", "Add a query parameter"),
+    FlagHelp::value("--remove", "<key>", "Remove a query parameter"),
+    FlagHelp::switch("--sort-params", "Sort the query parameters by key, then value"),
+    FlagHelp::value("--merge-query", "<query>", "Merge in the parameters of another query"),
+    FlagHelp::value(
+        "--on-conflict",
+        "<policy>",
+        "For a key already present: keep, overwrite or append",
+    )
+    .default("keep")
+    .choices(&["keep", "overwrite", "append"]),
+    FlagHelp::value("--scheme", "<rule>", "Set the scheme, or s/find/replace/[gi] in it"),
+    FlagHelp::value("--userinfo", "<rule>", "Set the userinfo, or s/find/replace/[gi] in it"),
+    FlagHelp::value("--host", "<rule>", "Set the host, or s/find/replace/[gi] in it"),
+    FlagHelp::value("--port", "<rule>", "Set the port, or s/find/replace/[gi] in it"),
+    FlagHelp::value("--path", "<rule>", "Set the path, or s/find/replace/[gi] in it"),
+    FlagHelp::value("--query", "<rule>", "Set the whole query, or s/find/replace/[gi] in it"),
+    FlagHelp::value("--fragment", "<rule>", "Set the fragment, or s/find/replace/[gi] in it"),
+    FlagHelp::value("--keep", "<key>", "Keep this tracking parameter; repeatable"),
+    FlagHelp::value("--extra", "<key>", "Also remove this parameter; repeatable"),
+    FlagHelp::switch("--decode-dots", "Count %2E as a dot when removing dot segments"),
+    FlagHelp::switch("--keep-escapes", "Leave %XX escapes as they are"),
+    FlagHelp::switch("--keep-default-port", "Keep a port equal to the scheme's default"),
+    FlagHelp::switch("--keep-dot-segments", "Leave . and .. segments in the path"),
+    FlagHelp::switch("--strip-fragment", "Drop the fragment"),
+    FlagHelp::switch("--normalize-escapes", "Only normalize the escapes"),
+    FlagHelp::switch("--show-dupes", "Print the groups of duplicates, not the unique URLs"),
+    FlagHelp::value("--hash", "<hash>", "Hash the key: none, fnv or sha256")
+        .default("none")
+        .choices(&["none", "fnv", "sha256"]),
+    FlagHelp::switch("--keep-param-order", "Leave the query parameters unsorted"),
+    FlagHelp::switch("--keep-fragment", "Keep the fragment in the key"),
+    FlagHelp::value("--key", "<secret>", "Secret the anonymize tokens are keyed with"),
+    FlagHelp::value("--keep-params", "<names>", "Comma-separated parameters left as they are"),
+    FlagHelp::value("--path-depth", "<n>", "Path segments left as they are (default: all)"),
+    FlagHelp::switch("--hash-host", "Replace the host with a token too"),
+    FlagHelp::value("--by", "<order>", "hierarchy, length or alpha")
+        .default("hierarchy")
+        .choices(&["hierarchy", "length", "alpha"]),
+    FlagHelp::switch("--reverse", "Sort descending"),
+    FlagHelp::switch("--offsets", "Print each URL's byte offset and a tab before it"),
+    FlagHelp::switch("--url-safe", "Use the URL-safe alphabet, - and _"),
+    FlagHelp::switch("--no-pad", "Leave out the = padding"),
+    FlagHelp::switch("--upper", "Uppercase hex digits"),
+    FlagHelp::value("--prefix", "<text>", "Write this before every byte, as in 0x"),
+    FlagHelp::value("--separator", "<text>", "Write this between bytes"),
+    FlagHelp::value("--from-json", "<object>", "Start from the pairs of a flat JSON object"),
+    FlagHelp::switch("--json", "Take each argument as a flat JSON object of pairs")
+        .only_for("formbody"),
+    FlagHelp::switch("--ignore-fragment", "Compare as if neither URL had a fragment"),
+    FlagHelp::switch("--ignore-param-order", "Compare query parameters in any order"),
+    FlagHelp::switch("--normalize", "Normalize both URLs before comparing"),
+    FlagHelp::value("--batch", "<path>", "Score every URL in a file, best match first")
+        .only_for("similar"),
+    FlagHelp::switch("--before-query", "Join onto a URL with a query or fragment, keeping it"),
+    FlagHelp::value("--vars-json", "<object>", "Take variables from a JSON object first"),
+    FlagHelp::switch("--strict", "Fail on a variable with no value instead of leaving it out"),
+    FlagHelp::value("--file", "<path>", "The corpus, one URL per line").only_for("bench"),
+    FlagHelp::value("--op", "<operation>", "The operation to time")
+        .default("encode")
+        .choices(OPERATIONS),
+    FlagHelp::value("--iterations", "<n>", "Timed passes over the corpus").default("10"),
+    FlagHelp::switch("--json", "Print the report as a JSON object").only_for("bench"),
+];
+
+/// The `FLAG_HELP` entry for `name` as `command` takes it
+fn flag_help(command: &str, name: &str) -> Option<&'static FlagHelp> {
+    let entries = || FLAG_HELP.iter().filter(move |flag| flag.name == name);
+    entries()
+        .find(|flag| flag.command == Some(command))
+        .or_else(|| entries().find(|flag| flag.command.is_none()))
+}
+
+/// CommandHelp documents an operation or subcommand for `--help`
+struct CommandHelp {
+    name: &'static str,
+    summary: &'static str,
+    /// What follows the command name
+    args: &'static str,
+    /// Its own flags, besides `GLOBAL_FLAGS` and for an operation
+    /// `INPUT_OUTPUT_FLAGS`
+    flags: &'static [&'static str],
+    examples: &'static [&'static str],
+}
+
+/// What `--help` says about each command, operations first in
+/// `OPERATIONS` order
+const COMMAND_HELP: &[CommandHelp] = &[
+    CommandHelp {
+        name: "encode",
+        summary: "Percent-encode text; the default operation",
+        args: "<url_or_text|->",
+        flags: &[
+            "--form",
+            "--rfc3986",
+            "--component",
+            "--safe",
+            "--unsafe",
+            "--no-double-encode",
+            "--url",
+            "--lower-hex",
+            "--idna",
+            "--codec",
+        ],
+        examples: &["url_encoder encode 'a b&c'", "url_encoder encode --component segment 'a/b'"],
+    },
+    CommandHelp {
+        name: "decode",
+        summary: "Decode percent-escapes",
+        args: "<url_or_text|->",
+        flags: &[
+            "--form",
+            "--rfc3986",
+            "--lenient",
+            "--lossy",
+            "--bytes",
+            "--hexdump",
+            "--recursive",
+            "--max-depth",
+            "--codec",
+        ],
+        examples: &["url_encoder decode 'caf%C3%A9'", "url_encoder decode --recursive 'a%2520b'"],
+    },
+    CommandHelp {
+        name: "analyze",
+        summary: "Report a URL's components, encoding and risks",
+        args: "<url|->",
+        flags: &[
+            "--format",
+            "--json",
+            "--csv",
+            "--no-header",
+            "--summary",
+            "--per-url",
+            "--group-by",
+            "--redact",
+            "--redact-param",
+            "--chars",
+            "--entropy-threshold",
+            "--fail-on-risk",
+            "--max-length",
+            "--max-host-length",
+            "--max-label-length",
+            "--max-value-length",
+            "--fail-on-limit",
+            "--display-unicode",
+            "--suffix-list",
+        ],
+        examples: &[
+            "url_encoder analyze 'https://example.com/?next=//evil.test'",
+            "url_encoder analyze --batch urls.txt --summary --format html -o report.html",
+        ],
+    },
+    CommandHelp {
+        name: "params",
+        summary: "List the decoded query parameters",
+        args: "<url|->",
+        flags: &["--group"],
+        examples: &["url_encoder params 'https://e.com/?a=1&b=x%20y&a=2' --group"],
+    },
+    CommandHelp {
+        name: "rewrite",
+        summary: "Edit the query parameters and rewrite the other components",
+        args: "<url|->",
+        flags: &[
+            "--set",
+            "--append",
+            "--remove",
+            "--sort-params",
+            "--merge-query",
+            "--on-conflict",
+            "--scheme",
+            "--userinfo",
+            "--host",
+            "--port",
+            "--path",
+            "--query",
+            "--fragment",
+        ],
+        examples: &[
+            "url_encoder rewrite 'https://e.com/?a=1' --set a=2 --append b=3",
+            "url_encoder rewrite 'http://www.e.com/index.html#top' --scheme https \
+             --host 's/^www\\.//' --path 's/index\\.html$//' --fragment ''",
+        ],
+    },
+    CommandHelp {
+        name: "clean",
+        summary: "Strip tracking parameters",
+        args: "<url|->",
+        flags: &["--keep", "--extra"],
+        examples: &["url_encoder clean 'https://e.com/?id=7&utm_source=mail' --keep utm_source"],
+    },
+    CommandHelp {
+        name: "normalize",
+        summary: "Rewrite a URL in RFC 3986 normal form",
+        args: "<url|->",
+        flags: &[
+            "--sort-params",
+            "--decode-dots",
+            "--idna",
+            "--keep-escapes",
+            "--keep-default-port",
+            "--keep-dot-segments",
+            "--strip-fragment",
+            "--normalize-escapes",
+        ],
+        examples: &["url_encoder normalize 'HTTP://Example.COM:80/a/../b?%7e'"],
+    },
+    CommandHelp {
+        name: "validate",
+        summary: "Check that a URL parses; exit status 3 if not",
+        args: "<url|->",
+        flags: &[],
+        examples: &["if url_encoder \"$url\" validate -q; then echo ok; fi"],
+    },
+    CommandHelp {
+        name: "check",
+        summary: "Check that encode and decode undo each other; exit status 3 if not",
+        args: "<url_or_text|->",
+        flags: &["--form", "--rfc3986", "--safe", "--unsafe", "--lower-hex"],
+        examples: &["url_encoder check 'a%20b%2Bc'", "url_encoder check --form 'a+b'"],
+    },
+    CommandHelp {
+        name: "to-file-url",
+        summary: "Turn a path into a file: URL",
+        args: "<path>",
+        flags: &[],
+        examples: &["url_encoder to-file-url '/tmp/a b.txt'"],
+    },
+    CommandHelp {
+        name: "from-file-url",
+        summary: "Turn a file: URL into a path",
+        args: "<url>",
+        flags: &[],
+        examples: &["url_encoder from-file-url 'file:///tmp/a%20b.txt'"],
+    },
+    CommandHelp {
+        name: "data-decode",
+        summary: "Write the payload of a data: URL",
+        args: "<url|->",
+        flags: &[],
+        examples: &["url_encoder data-decode 'data:text/plain;base64,aGk='"],
+    },
+    CommandHelp {
+        name: "b64encode",
+        summary: "Base64-encode text",
+        args: "<text|->",
+        flags: &["--url-safe", "--no-pad"],
+        examples: &["url_encoder b64encode 'hi?' --url-safe --no-pad"],
+    },
+    CommandHelp {
+        name: "b64decode",
+        summary: "Decode base64",
+        args: "<text|->",
+        flags: &["--url-safe", "--no-pad"],
+        examples: &["url_encoder b64decode 'aGk_'"],
+    },
+    CommandHelp {
+        name: "hexencode",
+        summary: "Hex-encode text",
+        args: "<text|->",
+        flags: &["--upper", "--prefix", "--separator"],
+        examples: &["url_encoder hexencode hi --prefix 0x --separator ' '"],
+    },
+    CommandHelp {
+        name: "hexdecode",
+        summary: "Decode hex",
+        args: "<text|->",
+        flags: &["--prefix", "--separator"],
+        examples: &["url_encoder hexdecode 6869"],
+    },
+    CommandHelp {
+        name: "html-encode",
+        summary: "Escape text for HTML",
+        args: "<text|->",
+        flags: &[],
+        examples: &["url_encoder html-encode '<a href=\"?a=1&b=2\">'"],
+    },
+    CommandHelp {
+        name: "html-decode",
+        summary: "Decode HTML character references",
+        args: "<text|->",
+        flags: &[],
+        examples: &["url_encoder html-decode '&lt;b&gt; &amp; &#x263A;'"],
+    },
+    CommandHelp {
+        name: "recase-escapes",
+        summary: "Rewrite the hex digits of every escape in one case",
+        args: "<text|->",
+        flags: &["--lower-hex"],
+        examples: &["url_encoder recase-escapes 'a%2fb%c3%a9'"],
+    },
+    CommandHelp {
+        name: "dedupe",
+        summary: "Drop batch lines that normalize to one already seen",
+        args: "<url|->",
+        flags: &[
+            "--sort-params",
+            "--decode-dots",
+            "--idna",
+            "--keep-escapes",
+            "--keep-default-port",
+            "--keep-dot-segments",
+            "--strip-fragment",
+            "--show-dupes",
+        ],
+        examples: &["url_encoder dedupe --batch urls.txt --show-dupes"],
+    },
+    CommandHelp {
+        name: "cache-key",
+        summary: "Print the canonical form a cache should key a URL by",
+        args: "<url|->",
+        flags: &[
+            "--hash",
+            "--keep",
+            "--extra",
+            "--keep-param-order",
+            "--keep-fragment",
+            "--decode-dots",
+            "--idna",
+        ],
+        examples: &["url_encoder cache-key --batch urls.txt --hash sha256"],
+    },
+    CommandHelp {
+        name: "anonymize",
+        summary: "Replace identifying values with keyed tokens, keeping the URL's shape",
+        args: "<url|->",
+        flags: &["--key", "--keep-params", "--path-depth", "--hash-host"],
+        examples: &["url_encoder anonymize --batch access.log --key \"$KEY\" --path-depth 1"],
+    },
+    CommandHelp {
+        name: "sort",
+        summary: "Sort batch lines",
+        args: "<url|->",
+        flags: &["--by", "--reverse", "--suffix-list"],
+        examples: &["url_encoder sort --batch urls.txt --by length --reverse"],
+    },
+    CommandHelp {
+        name: "extract",
+        summary: "Find the URLs in free text",
+        args: "<text|->",
+        flags: &["--offsets"],
+        examples: &["url_encoder extract --file notes.txt --offsets"],
+    },
+    CommandHelp {
+        name: "query-build",
+        summary: "Build a query string from pairs",
+        args: "[key=value|key]...",
+        flags: &["--from-json"],
+        examples: &["url_encoder query-build q='a b' page=2"],
+    },
+    CommandHelp {
+        name: "form",
+        summary: "Build a form body the way curl --data-urlencode does",
+        args: "[content|=content|name=content|[name]@file]...",
+        flags: &[],
+        examples: &["url_encoder form 'msg=hello world' name@name.txt"],
+    },
+    CommandHelp {
+        name: "formbody",
+        summary: "Build an application/x-www-form-urlencoded body",
+        args: "[key=value|key]...",
+        flags: &["--json"],
+        examples: &["url_encoder formbody --json '{\"q\": \"a b\"}'"],
+    },
+    CommandHelp {
+        name: "equiv",
+        summary: "Tell whether two URLs are equivalent; exit status 1 if not",
+        args: "<url1> <url2>",
+        flags: &["--ignore-fragment", "--ignore-param-order"],
+        examples: &["url_encoder equiv 'HTTP://a.com:80/?x=1&y=2' 'http://a.com/?y=2&x=1' \
+                     --ignore-param-order"],
+    },
+    CommandHelp {
+        name: "diff",
+        summary: "Show how two URLs differ, part by part; exit status 1 if they do",
+        args: "<url1> <url2>",
+        flags: &["--normalize"],
+        examples: &["url_encoder diff 'https://a.com/?x=1' 'https://a.com/?x=2'"],
+    },
+    CommandHelp {
+        name: "similar",
+        summary: "Score how alike URLs are, from 0.000 to 1.000 for the same URL",
+        args: "<url1> <url2> | <url> --batch <path>",
+        flags: &["--batch", "--normalize"],
+        examples: &[
+            "url_encoder similar 'https://www.a.com/x/y' 'https://a.com/x'",
+            "url_encoder similar 'https://a.com/x' --batch urls.txt --normalize",
+        ],
+    },
+    CommandHelp {
+        name: "resolve",
+        summary: "Resolve a relative reference against a base URL",
+        args: "<base> <reference>",
+        flags: &[],
+        examples: &["url_encoder resolve 'https://a.com/b/c' '../d?e'"],
+    },
+    CommandHelp {
+        name: "join",
+        summary: "Append encoded path segments to a base URL",
+        args: "<base> <segment>...",
+        flags: &["--before-query"],
+        examples: &["url_encoder join 'https://a.com/api' users 'a b'"],
+    },
+    CommandHelp {
+        name: "expand",
+        summary: "Expand an RFC 6570 URI template",
+        args: "<template> [name=value|name=a,b,c]...",
+        flags: &["--vars-json", "--strict"],
+        examples: &[
+            "url_encoder expand 'https://api.example.com/users/{id}/posts{?page}' id=42 page=2",
+            "url_encoder expand '{/path*}{?tags*}' path=a,b \
+             --vars-json '{\"tags\": [\"x\", \"y\"]}'",
+        ],
+    },
+    CommandHelp {
+        name: "bench",
+        summary: "Time an operation over a corpus of URLs, printing no results",
+        args: "--file <path>",
+        flags: &["--file", "--op", "--iterations", "--json"],
+        examples: &["url_encoder bench --file urls.txt --op encode --iterations 10"],
+    },
+    CommandHelp {
+        name: "repl",
+        summary: "Apply an operation to each line typed, in one session",
+        args: "",
+        flags: &[],
+        examples: &[
+            "url_encoder repl",
+            "url_encoder repl -v --form",
+            "printf ':mode decode\\na%20b\\n' | url_encoder repl",
+        ],
+    },
+    CommandHelp {
+        name: "completions",
+        summary: "Write a tab-completion script for bash, zsh or fish",
+        args: "<bash|zsh|fish>",
+        flags: &[],
+        examples: &[
+            "url_encoder completions bash > /etc/bash_completion.d/url_encoder",
+            "url_encoder completions zsh > \"${fpath[1]}/_url_encoder\"",
+            "url_encoder completions fish > ~/.config/fish/completions/url_encoder.fish",
+        ],
+    },
+];
+
+impl CommandHelp {
+    fn find(name: &str) -> Option<&'static CommandHelp> {
+        COMMAND_HELP.iter().find(|command| command.name == name)
+    }
+
+    fn is_operation(&self) -> bool {
+        OPERATIONS.contains(&self.name)
+    }
+
+    /// The flags the command takes besides `GLOBAL_FLAGS`: its own and,
+    /// for an operation, the input and output flags
+    fn accepted_flags(&self) -> Vec<&'static str> {
+        let shared = if self.is_operation() { INPUT_OUTPUT_FLAGS } else { &[] };
+        self.flags.iter().chain(shared).copied().collect()
+    }
+
+    /// Every flag the command takes: `accepted_flags`, then the global
+    /// flags
+    fn all_flags(&self) -> Vec<&'static FlagHelp> {
+        self.accepted_flags()
+            .into_iter()
+            .chain(GLOBAL_FLAGS.iter().copied())
+            .map(|name| flag_help(self.name, name).expect("every flag has help"))
+            .collect()
+    }
+
+    /// Everything `url_encoder <name> --help` prints
+    fn render(&self) -> String {
+        let mut help = format!("url_encoder {}: {}\n\n", self.name, self.summary);
+        if self.is_operation() {
+            help += &format!("Usage: url_encoder {} [flags] [--] {}\n", self.name, self.args);
+        } else {
+            help += &format!("Usage: url_encoder {} {} [flags]\n", self.name, self.args);
+        }
+        if !self.flags.is_empty() {
+            help += &format!("\nFlags:\n{}", flag_table(self.name, self.flags));
+        }
+        if self.is_operation() {
+            help += &format!("\nInput and output:\n{}", flag_table(self.name, INPUT_OUTPUT_FLAGS));
+        }
+        help += &format!("\nGlobal flags:\n{}", flag_table(self.name, GLOBAL_FLAGS));
+        help += "\nExamples:\n";
+        for example in self.examples {
+            help += &format!("  {}\n", example);
+        }
+        help
+    }
+}
+
+/// Aligned `--help` lines for `flags` of `command`, each with its default
+fn flag_table(command: &str, flags: &[&str]) -> String {
+    let rows: Vec<(String, String)> = flags
+        .iter()
+        .map(|name| {
+            let flag = flag_help(command, name).expect("every flag has help");
+            let help = match flag.default {
+                Some(default) => format!("{} (default: {})", flag.help, default),
+                None => flag.help.to_string(),
+            };
+            (flag.synopsis(), help)
+        })
+        .collect();
+    let width = rows.iter().map(|(synopsis, _)| synopsis.len()).max().unwrap_or(0);
+
+    rows.iter()
+        .map(|(synopsis, help)| format!("  {:width$}  {}\n", synopsis, help, width = width))
+        .collect()
+}
+
+/// Everything `url_encoder --help` prints
+fn overview_help() -> String {
+    let width = COMMAND_HELP.iter().map(|command| command.name.len()).max().unwrap_or(0);
+    let list = |operations: bool| -> String {
+        COMMAND_HELP
+            .iter()
+            .filter(|command| command.is_operation() == operations)
+            .map(|command| {
+                format!("  {:width$}  {}\n", command.name, command.summary, width = width)
+            })
+            .collect()
+    };
+
+    format!(
+        "url_encoder: percent-encode, decode, analyze and rewrite URLs\n\n{}\n\n\
+         Operations:\n{}\nCommands:\n{}\nGlobal flags:\n{}\n{}\n\n\
+         Run 'url_encoder <command> --help' for its flags and examples.\n",
+        USAGE,
+        list(true),
+        list(false),
+        flag_table("", GLOBAL_FLAGS),
+        EXIT_STATUS_HELP
+    )
+}
+
+/// Every feature of the crate, with whether this build has it
+const FEATURES: [(&str, bool); 4] = [
+    ("std", cfg!(feature = "std")),
+    ("serde", cfg!(feature = "serde")),
+    ("wasm", cfg!(feature = "wasm")),
+    ("ffi", cfg!(feature = "ffi")),
+];
+
+/// What `--version` prints: the crate version and the enabled features
+fn version_text() -> String {
+    let features: Vec<&str> = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    let features = if features.is_empty() { "none".to_string() } else { features.join(", ") };
+    format!("url_encoder {}\nfeatures: {}", env!("CARGO_PKG_VERSION"), features)
+}
+
+/// `completions`: a tab-completion script for the shell in `args[0]`
+///
+/// Scripts are generated from `COMMAND_HELP` and `FLAG_HELP`, so they offer
+/// what `--help` documents. Until the command is typed they offer every
+/// command and every operation flag, since an input alone means `encode`.
+fn completions(args: &[String]) -> Result<String, String> {
+    let [shell] = args else {
+        return Err("completions takes a shell: bash, zsh or fish".to_string());
+    };
+    match shell.as_str() {
+        "bash" => Ok(bash_completions()),
+        "zsh" => Ok(zsh_completions()),
+        "fish" => Ok(fish_completions()),
+        _ => Err(format!("Unknown shell: {}. Use 'bash', 'zsh', or 'fish'", shell)),
+    }
+}
+
+/// The flags offered before a command is typed
+fn flags_before_command() -> Vec<&'static FlagHelp> {
+    GLOBAL_FLAGS
+        .iter()
+        .chain(OPERATION_FLAGS)
+        .map(|name| flag_help("", name).expect("every flag has help"))
+        .collect()
+}
+
+fn bash_completions() -> String {
+    let words = |flags: &[&FlagHelp]| -> String {
+        let forms: Vec<&str> = flags.iter().flat_map(|flag| flag.forms()).collect();
+        forms.join(" ")
+    };
+    let names: Vec<&str> = COMMAND_HELP.iter().map(|command| command.name).collect();
+
+    let mut values = String::new();
+    let mut paths = Vec::new();
+    let mut free = Vec::new();
+    for flag in FLAG_HELP.iter().filter(|flag| flag.value.is_some()) {
+        let pattern = flag.forms().join("|");
+        if !flag.choices.is_empty() {
+            let choices = flag.choices.join(" ");
+            values += &format!(
+                "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+                pattern, choices
+            );
+        } else if flag.value == Some("<path>") {
+            paths.push(pattern);
+        } else {
+            free.push(pattern);
+        }
+    }
+    values += &format!(
+        "        {}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n",
+        paths.join("|")
+    );
+    values += &format!("        {}) return ;;\n", free.join("|"));
+
+    let mut flags = String::new();
+    for command in COMMAND_HELP {
+        let all = words(&command.all_flags());
+        flags += &format!("        {}) flags=\"{}\" ;;\n", command.name, all);
+    }
+    flags += &format!("        *) flags=\"{}\" ;;\n", words(&flags_before_command()));
+
+    format!(
+        "# bash completion for url_encoder, from `url_encoder completions bash`
+_url_encoder() {{
+    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"
+    local command=\"\" word flags
+    for word in \"${{COMP_WORDS[@]:1:COMP_CWORD-1}}\"; do
+        case \"$word\" in
+            --) break ;;
+            {}) command=\"$word\"; break ;;
+        esac
+    done
+    case \"$prev\" in
+{}    esac
+    case \"$command\" in
+{}    esac
+    if [[ \"$cur\" == -* ]]; then
+        COMPREPLY=($(compgen -W \"$flags\" -- \"$cur\"))
+    elif [[ -z \"$command\" ]]; then
+        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))
+    elif [[ \"$command\" == completions ]]; then
+        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))
+    fi
+}}
+complete -F _url_encoder url_encoder
+",
+        names.join("|"),
+        values,
+        flags,
+        names.join(" "),
+        SHELLS.join(" ")
+    )
+}
+
+/// `text` inside a single-quoted zsh word, with the brackets `_arguments`
+/// reads escaped
+fn zsh_quote(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace('\'', "'\\''")
+}
+
+/// `_arguments` specs for `flags`, one per form, each starting a line
+fn zsh_specs(flags: &[&FlagHelp]) -> String {
+    let mut specs = String::new();
+    for flag in flags {
+        let help = zsh_quote(flag.help);
+        let argument = flag.value.map(|value| {
+            let action = if !flag.choices.is_empty() {
+                format!("({})", flag.choices.join(" "))
+            } else if value == "<path>" {
+                "_files".to_string()
+            } else {
+                " ".to_string()
+            };
+            format!(":{}:{}", value.trim_matches(|c| c == '<' || c == '>'), action)
+        });
+        let argument = argument.unwrap_or_default();
+        let mut spec = |form: &str, attached: &str| {
+            specs += &format!(" \\\n                '*{}{}[{}]{}'", form, attached, help, argument);
+        };
+        if let Some(short) = flag.short {
+            spec(short, if flag.value.is_some() { "+" } else { "" });
+        }
+        spec(flag.name, if flag.value.is_some() { "=" } else { "" });
+    }
+    specs
+}
+
+fn zsh_completions() -> String {
+    let names: Vec<&str> = COMMAND_HELP.iter().map(|command| command.name).collect();
+    let described: String = COMMAND_HELP
+        .iter()
+        .map(|command| format!("        '{}:{}'\n", command.name, zsh_quote(command.summary)))
+        .collect();
+
+    let mut cases = String::new();
+    for command in COMMAND_HELP {
+        let positional = if command.name == "completions" {
+            format!("'*:shell:({})'", SHELLS.join(" "))
+        } else {
+            "'*:argument:_default'".to_string()
+        };
+        cases += &format!(
+            "        ({})\n            _arguments{} \\\n                {}\n            ;;\n",
+            command.name,
+            zsh_specs(&command.all_flags()),
+            positional
+        );
+    }
+    cases += &format!(
+        "        (*)\n            _arguments{} \\\n                '*: :->command'\n            \
+         [[ $state == command ]] && _describe -t commands command commands\n            ;;\n",
+        zsh_specs(&flags_before_command())
+    );
+
+    format!(
+        "#compdef url_encoder
+# zsh completion for url_encoder, from `url_encoder completions zsh`
+
+_url_encoder() {{
+    local -a commands
+    commands=(
+{}    )
+    local command word context state state_descr line
+    local -A opt_args
+    for word in ${{words[2,CURRENT-1]}}; do
+        case $word in
+            (--) break ;;
+            ({}) command=$word; break ;;
+        esac
+    done
+    case $command in
+{}    esac
+}}
+
+_url_encoder \"$@\"
+",
+        described,
+        names.join("|"),
+        cases
+    )
+}
+
+/// `text` as a single-quoted fish string
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish_completions() -> String {
+    let mut script =
+        "# fish completion for url_encoder, from `url_encoder completions fish`\n".to_string();
+    script += "complete -c url_encoder -f\n";
+    for command in COMMAND_HELP {
+        script += &format!(
+            "complete -c url_encoder -n '__fish_use_subcommand' -a {} -d {}\n",
+            command.name,
+            fish_quote(command.summary)
+        );
+    }
+    script += &format!(
+        "complete -c url_encoder -n '__fish_seen_subcommand_from completions' -a {}\n",
+        fish_quote(&SHELLS.join(" "))
+    );
+
+    for flag in FLAG_HELP {
+        let commands: Vec<&str> = COMMAND_HELP
+            .iter()
+            .filter(|command| command.all_flags().iter().any(|f| std::ptr::eq(*f, flag)))
+            .map(|command| command.name)
+            .collect();
+        let mut line = "complete -c url_encoder".to_string();
+        if !GLOBAL_FLAGS.contains(&flag.name) {
+            let mut condition = format!("__fish_seen_subcommand_from {}", commands.join(" "));
+            if flag.command.is_none() && OPERATION_FLAGS.contains(&flag.name) {
+                condition = format!("__fish_use_subcommand; or {}", condition);
+            }
+            line += &format!(" -n {}", fish_quote(&condition));
+        }
+        if let Some(short) = flag.short {
+            line += &format!(" -s {}", &short[1..]);
+        }
+        line += &format!(" -l {}", &flag.name[2..]);
+        if !flag.choices.is_empty() {
+            line += &format!(" -x -a {}", fish_quote(&flag.choices.join(" ")));
+        } else if flag.value == Some("<path>") {
+            line += " -r -F";
+        } else if flag.value.is_some() {
+            line += " -x";
+        }
+        script += &format!("{} -d {}\n", line, fish_quote(flag.help));
+    }
+    script
+}
+
+/// Split command-line arguments into positionals and flags, keeping each
+/// value flag next to its value
+///
+/// Flags may come before, between or after the positionals; everything
+/// after `--` is positional. Short options are spelled out (`-o` is
+/// `--output`) and `--flag=value` is split in two. Fails for a value
+/// flag without its value or an unknown short option.
+fn split_args(args: &[String]) -> Result<(Vec<String>, Vec<String>), String> {
+    let mut positionals = Vec::new();
+    let mut flags = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positionals.extend(args.by_ref().cloned());
+        } else if let Some((flag, value)) = arg.split_once('=').filter(|_| arg.starts_with("--")) {
+            if !VALUE_FLAGS.contains(&flag) {
+                return Err(format!("{} does not take a value", flag));
+            }
+            flags.push(flag.to_string());
+            flags.push(value.to_string());
+        } else if arg == "-o" {
+            flags.push("--output".to_string());
+            flags.push(args.next().ok_or("-o needs a value")?.clone());
+        } else if arg == "-0" {
+            flags.push("--null".to_string());
+        } else if arg == "-q" {
+            flags.push("--quiet".to_string());
+        } else if arg == "-h" {
+            flags.push("--help".to_string());
+        } else if arg == "-V" {
+            flags.push("--version".to_string());
+        } else if arg.len() > 1 && arg.starts_with('-') && arg[1..].bytes().all(|b| b == b'v') {
+            flags.extend((1..arg.len()).map(|_| "--verbose".to_string()));
+        } else if arg.starts_with("--") {
+            flags.push(arg.clone());
+            if VALUE_FLAGS.contains(&arg.as_str()) {
+                let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                flags.push(value.clone());
+            }
+        } else if arg.len() > 1 && arg.as_bytes()[1].is_ascii_alphabetic() && arg.starts_with('-') {
+            return Err(format!(
+                "Unknown option: {}. Put -- before an input that starts with -",
+                arg
+            ));
+        } else {
+            positionals.push(arg.clone());
+        }
+    }
+
+    Ok((positionals, flags))
+}
+
+/// Fail on the first flag that is neither global nor in `allowed`,
+/// suggesting the nearest one that is
+fn check_flags(flags: &[String], allowed: &[&str]) -> Result<(), String> {
+    let mut flags = flags.iter();
+
+    while let Some(flag) = flags.next() {
+        if VALUE_FLAGS.contains(&flag.as_str()) {
+            flags.next();
+        }
+        if GLOBAL_FLAGS.contains(&flag.as_str()) || allowed.contains(&flag.as_str()) {
+            continue;
+        }
+        let known: Vec<&str> = GLOBAL_FLAGS.iter().chain(allowed).copied().collect();
+        return Err(match suggestion(flag, &known) {
+            Some(name) => format!("Unknown flag: {}. Did you mean {}?", flag, name),
+            None => format!("Unknown flag: {}. See --help for the flags", flag),
+        });
+    }
+
+    Ok(())
+}
+
+/// Fail on the first flag that some operation takes but `operation`
+/// does not, e.g. `encode --fail-on-risk`
+fn check_operation_flags(flags: &[String], operation: &str) -> Result<(), String> {
+    let command = CommandHelp::find(operation).expect("every operation has help");
+    let accepted = command.accepted_flags();
+    let mut flags = flags.iter();
+
+    while let Some(flag) = flags.next() {
+        if VALUE_FLAGS.contains(&flag.as_str()) {
+            flags.next();
+        }
+        if GLOBAL_FLAGS.contains(&flag.as_str()) || accepted.contains(&flag.as_str()) {
+            continue;
+        }
+        return Err(format!(
+            "{} does not take {}. See 'url_encoder {} --help' for its flags",
+            operation, flag, operation
+        ));
+    }
+
+    Ok(())
+}
+
+/// Levenshtein distance between `a` and `b`, counted in bytes
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.as_bytes();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, &x) in a.as_bytes().iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &y) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(x != y);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(diagonal + 1).min(row[j] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// The candidate nearest to `name`, if it is near enough to be what was
+/// meant
+fn suggestion<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let limit = (name.trim_start_matches('-').len() / 3).max(2);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Where input can come from, for the usage error when there is none
+const INPUT_SOURCES: &str = "Give a URL or text, - to read stdin, or --file or --batch <path>";
+
+/// The input and operation named by `positionals`, in either order
+///
+/// With `--file` or `--batch` (`from_file`) the only positional is the
+/// operation. The input is `-`, stdin, when given as such or when stdin
+/// is piped and at most an operation is named. An operation name alone is
+/// taken as the operation, so it needs input from somewhere.
+fn input_and_operation(
+    positionals: &[String],
+    from_file: bool,
+    stdin_piped: bool,
+) -> Result<(Option<&str>, Option<&str>), String> {
+    let is_operation = |name: &str| OPERATIONS.contains(&name);
+    let count = if from_file { 1 } else { 2 };
+    if let Some(extra) = positionals.get(count) {
+        let names: Vec<&str> = SUBCOMMANDS.iter().map(|(name, _)| *name).collect();
+        return Err(match suggestion(&positionals[0], &names) {
+            Some(name) => format!("Unknown command: {}. Did you mean {}?", positionals[0], name),
+            None => format!("Unexpected argument: {}", extra),
+        });
+    }
+    let positionals: Vec<&str> = positionals.iter().map(String::as_str).collect();
+
+    match positionals[..] {
+        [] if from_file => Ok((None, None)),
+        [operation] if from_file => Ok((None, Some(operation))),
+        [] if stdin_piped => Ok((Some("-"), None)),
+        [operation] if is_operation(operation) && stdin_piped => Ok((Some("-"), Some(operation))),
+        [] => Err(format!("No input. {}", INPUT_SOURCES)),
+        [operation] if is_operation(operation) => {
+            Err(format!("No input for {}. {}", operation, INPUT_SOURCES))
+        }
+        [input] => Ok((Some(input), None)),
+        [operation, input] if is_operation(operation) && !is_operation(input) => {
+            Ok((Some(input), Some(operation)))
+        }
+        [input, operation] => Ok((Some(input), Some(operation))),
+        _ => unreachable!("at most two positionals"),
+    }
+}
+
+/// Every value given for a repeatable flag, as in `--keep a --keep b`
+fn flag_values<'a>(flags: &'a [String], name: &str) -> Vec<&'a str> {
+    flags
+        .windows(2)
+        .filter(|pair| pair[0] == name)
+        .map(|pair| pair[1].as_str())
+        .collect()
+}
+
+/// The `--scheme`, `--host` and other component flags as rules, in
+/// command-line order
+fn rewrite_rules(flags: &[String]) -> Result<Vec<RewriteRule>, String> {
+    let mut rules = Vec::new();
+
+    let mut flags = flags.iter();
+
+    while let Some(flag) = flags.next() {
+        if !VALUE_FLAGS.contains(&flag.as_str()) {
+            continue;
+        }
+        let Some(value) = flags.next() else {
+            break;
+        };
+        if let Some(part) = flag.strip_prefix("--").and_then(UrlPart::from_name) {
+            let rule =
+                RewriteRule::parse(part, value).map_err(|e| format!("Invalid {}: {}", flag, e))?;
+            rules.push(rule);
+        }
+    }
+    Ok(rules)
+}
+
+/// Value following `name` in the flag list, as in `--component query`
+fn flag_value<'a>(flags: &'a [String], name: &str) -> Option<&'a str> {
+    flags
+        .iter()
+        .position(|f| f == name)
+        .and_then(|i| flags.get(i + 1))
+        .map(|value| value.as_str())
+}
+
+/// Operations main() knows how to run on an input
+const OPERATIONS: &[&str] = &[
+    "encode",
+    "decode",
+    "analyze",
+    "params",
+    "rewrite",
+    "clean",
+    "normalize",
+    "validate",
+    "check",
+    "to-file-url",
+    "from-file-url",
+    "data-decode",
+    "b64encode",
+    "b64decode",
+    "hexencode",
+    "hexdecode",
+    "html-encode",
+    "html-decode",
+    "recase-escapes",
+    "dedupe",
+    "cache-key",
+    "anonymize",
+    "sort",
+    "extract",
+];
+
+fn unknown_operation(operation: &str) -> String {
+    let hint = match suggestion(operation, OPERATIONS) {
+        Some(name) => format!(" Did you mean {}?", name),
+        None => String::new(),
+    };
+    format!("Unknown operation: {}.{} Use one of: {}", operation, hint, OPERATIONS.join(", "))
+}
+
+/// The `--set`, `--append`, `--remove`, `--sort-params` and
+/// `--merge-query` flags, in command-line order
+fn query_edits(flags: &[String], policy: MergePolicy) -> Vec<QueryEdit> {
+    let mut edits = Vec::new();
+    let mut flags = flags.iter();
+
+    while let Some(flag) = flags.next() {
+        if flag == "--sort-params" {
+            edits.push(QueryEdit::Sort);
+        }
+        if !VALUE_FLAGS.contains(&flag.as_str()) {
+            continue;
+        }
+        let Some(value) = flags.next() else {
+            break;
+        };
+        match flag.as_str() {
+            "--set" => {
+                let (key, value) = QueryEdit::key_value(value);
+                edits.push(QueryEdit::Set(key, value));
+            }
+            "--append" => {
+                let (key, value) = QueryEdit::key_value(value);
+                edits.push(QueryEdit::Append(key, value));
+            }
+            "--remove" => edits.push(QueryEdit::Remove(value.clone())),
+            "--merge-query" => {
+                let query = value.strip_prefix('?').unwrap_or(value);
+                edits.push(QueryEdit::Merge(QueryMap::from(parse_query_pairs(query)), policy));
+            }
+            _ => {}
+        }
+    }
+
+    edits
+}
+
+/// One `key = value` line per query parameter; a key without `=` is
+/// printed alone
+fn params_lines(params: &[(String, Option<String>)]) -> Vec<String> {
+    params
+        .iter()
+        .map(|(key, value)| match value {
+            Some(value) => format!("{} = {}", key, value),
+            None => key.clone(),
+        })
+        .collect()
+}
+
+/// One `key: [a, b]` line per distinct key, keys in order of first appearance
+///
+/// An empty value is `""`, so `e=` gives `e: [""]` while a key without `=`
+/// gives `flag: []`.
+fn grouped_params_lines(params: &QueryMap) -> Vec<String> {
+    params
+        .groups()
+        .map(|(key, values)| {
+            let shown: Vec<&str> =
+                values.iter().map(|v| if v.is_empty() { "\"\"" } else { v.as_str() }).collect();
+            format!("{}: [{}]", key, shown.join(", "))
+        })
+        .collect()
+}
+
+/// Read-only memory maps of `--batch` files, for `--mmap`
+///
+/// This is the only unsafe code in the command line. A file is not mapped
+/// when it is empty, on platforms other than 64-bit Unix, or when the
+/// kernel refuses, as it does for pipes and some filesystems; callers then
+/// read it through a buffer. Another process truncating the file while it
+/// is mapped makes reads past the new end fail with SIGBUS.
+mod mapped {
+    use std::fs::File;
+    use std::io;
+
+    /// Mapped is a whole file mapped read-only into memory, unmapped on drop
+    pub(crate) struct Mapped {
+        ptr: *const u8,
+        len: usize,
+    }
+
+    impl Mapped {
+        pub(crate) fn new(file: &File) -> io::Result<Mapped> {
+            let len = usize::try_from(file.metadata()?.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+            if len == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty file"));
+            }
+            sys::map(file, len).map(|ptr| Mapped { ptr, len })
+        }
+
+        /// The mapped file
+        pub(crate) fn bytes(&self) -> &[u8] {
+            // SAFETY: `ptr` is a live read-only mapping of `len` bytes until
+            // `drop`, and the returned slice borrows `self`.
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+    }
+
+    impl Drop for Mapped {
+        fn drop(&mut self) {
+            sys::unmap(self.ptr, self.len);
+        }
+    }
+
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    mod sys {
+        use std::ffi::{c_int, c_void};
+        use std::fs::File;
+        use std::io;
+        use std::os::unix::io::AsRawFd;
+        use std::ptr;
+
+        // The same on Linux, macOS and the BSDs
+        const PROT_READ: c_int = 1;
+        const MAP_PRIVATE: c_int = 2;
+        const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+        extern "C" {
+            fn mmap(
+                addr: *mut c_void,
+                len: usize,
+                prot: c_int,
+                flags: c_int,
+                fd: c_int,
+                offset: i64,
+            ) -> *mut c_void;
+            fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        }
+
+        pub(super) fn map(file: &File, len: usize) -> io::Result<*const u8> {
+            // SAFETY: a fresh private read-only mapping of an open file
+            // aliases no Rust memory.
+            let ptr = unsafe {
+                mmap(ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0)
+            };
+            if ptr == MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(ptr as *const u8)
+        }
+
+        pub(super) fn unmap(ptr: *const u8, len: usize) {
+            // SAFETY: `ptr` and `len` come from `map`, and the slices
+            // borrowed from the mapping are gone.
+            unsafe { munmap(ptr as *mut c_void, len) };
+        }
+    }
+
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    mod sys {
+        use std::fs::File;
+        use std::io;
+
+        pub(super) fn map(_file: &File, _len: usize) -> io::Result<*const u8> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "no mmap on this platform"))
+        }
+
+        pub(super) fn unmap(_ptr: *const u8, _len: usize) {}
+    }
+}
+
+/// ANSI colors for terminal output
+///
+/// Renderers build plain text and `Styler` colors it afterwards, so output
+/// with color off is exactly what it was before.
+mod style {
+    use std::borrow::Cow;
+    use std::env;
+    use std::io::{self, IsTerminal};
+
+    const RESET: &str = "\x1b[0m";
+    const BOLD: &str = "\x1b[1m";
+    const DIM: &str = "\x1b[2m";
+    const RED: &str = "\x1b[31m";
+    const GREEN: &str = "\x1b[32m";
+    const YELLOW: &str = "\x1b[33m";
+    /// Bold magenta, for what encoding changed
+    const HIGHLIGHT: &str = "\x1b[1;35m";
+
+    /// ColorChoice is the `--color` setting
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) enum ColorChoice {
+        /// Color when stdout is a terminal and `NO_COLOR` is not set
+        Auto,
+        Always,
+        Never,
+    }
+
+    impl ColorChoice {
+        pub(crate) fn from_name(name: &str) -> Option<ColorChoice> {
+            match name {
+                "auto" => Some(ColorChoice::Auto),
+                "always" => Some(ColorChoice::Always),
+                "never" => Some(ColorChoice::Never),
+                _ => None,
+            }
+        }
+    }
+
+    /// Styler colors rendered text, or leaves it alone when disabled
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) struct Styler {
+        enabled: bool,
+    }
+
+    impl Styler {
+        /// `Always` and `Never` decide alone. `Auto` colors a terminal
+        /// unless `NO_COLOR` is set to a non-empty value; see
+        /// https://no-color.org.
+        pub(crate) fn new(choice: ColorChoice, is_terminal: bool, no_color: bool) -> Styler {
+            let enabled = match choice {
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+                ColorChoice::Auto => is_terminal && !no_color,
+            };
+            Styler { enabled }
+        }
+
+        /// A styler for stdout, checking whether it is a terminal
+        pub(crate) fn stdout(choice: ColorChoice) -> Styler {
+            Styler::for_output(choice, io::stdout().is_terminal())
+        }
+
+        /// A styler for output that is a terminal or not, checking
+        /// `NO_COLOR`
+        pub(crate) fn for_output(choice: ColorChoice, is_terminal: bool) -> Styler {
+            let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            Styler::new(choice, is_terminal, no_color)
+        }
+
+        fn paint<'a>(&self, code: &str, text: &'a str) -> Cow<'a, str> {
+            if self.enabled && !text.is_empty() {
+                Cow::Owned(format!("{}{}{}", code, text, RESET))
+            } else {
+                Cow::Borrowed(text)
+            }
+        }
+
+        /// `URLAnalysis::report_with` output with headings in bold,
+        /// warnings in red and notes in yellow
+        pub(crate) fn report(&self, report: &str) -> String {
+            if !self.enabled {
+                return report.to_string();
+            }
+            let lines: Vec<&str> = report.split('\n').collect();
+            let underline = |line: &str| !line.is_empty() && line.bytes().all(|b| b == b'=');
+            let styled: Vec<Cow<str>> = lines
+                .iter()
+                .enumerate()
+                .map(|(i, &line)| {
+                    let heading = underline(line)
+                        || lines.get(i + 1).is_some_and(|next| underline(next))
+                        || (line.ends_with(':') && !line.starts_with(' '));
+                    if heading {
+                        self.paint(BOLD, line)
+                    } else if line.starts_with("Warning: ") {
+                        self.paint(RED, line)
+                    } else if line.starts_with("Note: ") {
+                        self.paint(YELLOW, line)
+                    } else {
+                        Cow::Borrowed(line)
+                    }
+                })
+                .collect();
+            styled.join("\n")
+        }
+
+        /// A `Name: value` line, such as a URL component, with the name
+        /// dimmed
+        pub(crate) fn field(&self, line: &str) -> String {
+            match line.split_once(": ") {
+                Some((name, value)) if self.enabled => {
+                    format!("{}: {}", self.paint(DIM, name), value)
+                }
+                _ => line.to_string(),
+            }
+        }
+
+        /// A `Diff::lines` line, removals red and additions green
+        pub(crate) fn diff_line<'a>(&self, line: &'a str) -> Cow<'a, str> {
+            match line.as_bytes().first() {
+                Some(b'-') => self.paint(RED, line),
+                Some(b'+') => self.paint(GREEN, line),
+                _ => Cow::Borrowed(line),
+            }
+        }
+
+        /// A `params_lines` or `grouped_params_lines` line with the key in
+        /// bold
+        pub(crate) fn param_line(&self, line: &str) -> String {
+            let split = line.find(" = ").or_else(|| line.find(": ["));
+            match split {
+                Some(at) if self.enabled => {
+                    format!("{}{}", self.paint(BOLD, &line[..at]), &line[at..])
+                }
+                _ => self.paint(BOLD, line).into_owned(),
+            }
+        }
+
+        /// `encoded` with what encoding `original` changed highlighted:
+        /// each escape or `+` standing for a character of `original`
+        ///
+        /// Escapes already in `original` and kept as they were are not
+        /// highlighted. If `encoded` does not line up with `original`,
+        /// every escape is.
+        pub(crate) fn changes(&self, original: &str, encoded: &str) -> String {
+            if !self.enabled {
+                return encoded.to_string();
+            }
+            let mut out = String::with_capacity(encoded.len() * 2);
+            let mut rest = encoded;
+            for c in original.chars() {
+                let mut buf = [0; 4];
+                let c = &*c.encode_utf8(&mut buf);
+                let len = if rest.starts_with(c) {
+                    out.push_str(c);
+                    rest = &rest[c.len()..];
+                    continue;
+                } else if rest.starts_with('%') {
+                    3 * c.len()
+                } else {
+                    rest.chars().next().map_or(0, char::len_utf8)
+                };
+                match rest.get(..len) {
+                    Some(changed) if len > 0 => {
+                        out.push_str(&self.paint(HIGHLIGHT, changed));
+                        rest = &rest[len..];
+                    }
+                    _ => return self.escapes(encoded),
+                }
+            }
+            if !rest.is_empty() {
+                return self.escapes(encoded);
+            }
+            out
+        }
+
+        /// `text` with every `%XX`-looking escape highlighted
+        fn escapes(&self, text: &str) -> String {
+            let mut out = String::with_capacity(text.len() * 2);
+            let mut rest = text;
+            while let Some(at) = rest.find('%') {
+                out.push_str(&rest[..at]);
+                let end = rest.len().min(at + 3);
+                let escape = rest.get(at..end).unwrap_or(&rest[at..at + 1]);
+                out.push_str(&self.paint(HIGHLIGHT, escape));
+                rest = &rest[at + escape.len()..];
+            }
+            out.push_str(rest);
+            out
+        }
+    }
+}
+
+/// The `--color` setting
+fn color_choice(flags: &[String]) -> Result<ColorChoice, String> {
+    match flag_value(flags, "--color") {
+        Some(name) => ColorChoice::from_name(name)
+            .ok_or_else(|| format!("Unknown --color: {}. Use 'auto', 'always', or 'never'", name)),
+        None => Ok(ColorChoice::Auto),
+    }
+}
+
+/// OutputFormat selects how `analyze` writes its results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// `URLAnalysis::report_with` and the components
+    Text,
+    /// `URLEncoder::analyze_json`, one object per line
+    Json,
+    /// `URLEncoder::analyze_csv_row` under `ANALYZE_CSV_HEADER`
+    Csv,
+    /// `URLEncoder::analyze_markdown_with`
+    Markdown,
+    /// `jai::html_report`
+    Html,
+    /// `URLEncoder::analyze_yaml`, one document per URL
+    Yaml,
+}
+
+impl OutputFormat {
+    fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            "markdown" | "md" => Some(OutputFormat::Markdown),
+            "html" => Some(OutputFormat::Html),
+            "yaml" | "yml" => Some(OutputFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// Settings parsed from the command line, shared by every input processed
+struct CliOptions {
+    mode: Option<EncodeMode>,
+    encode_set: EncodeSet,
+    /// `--lower-hex`: encoding and `recase-escapes` write `%2f`
+    encode_options: EncodeOptions,
+    component: Option<Component>,
+    idempotent: bool,
+    /// `--url`: `encode` takes the input as a whole URL; see `encode_url`
+    url: bool,
+    decode: DecodeOptions,
+    lossy: bool,
+    recursive: bool,
+    max_depth: usize,
+    jobs: usize,
+    /// `--format`, or the `--json` and `--csv` shorthands
+    format: OutputFormat,
+    csv_header: bool,
+    group: bool,
+    edits: Vec<QueryEdit>,
+    /// The component flags such as `--host`, in command-line order
+    rules: Vec<RewriteRule>,
+    keep: Vec<String>,
+    extra: Vec<String>,
+    /// Set by `--redact` or any `--redact-param`
+    redactor: Option<Redactor>,
+    anonymizer: Anonymizer,
+    normalize: NormalizeOptions,
+    cache_key: CacheKeyOptions,
+    /// `--normalize-escapes`: `normalize` only runs `normalize_percent_encoding`
+    escapes_only: bool,
+    /// `--idna`: `encode` converts the whole URL to ASCII
+    idna: bool,
+    /// `--display-unicode`: `analyze` shows `xn--` hosts in Unicode too
+    display_unicode: bool,
+    /// `--suffix-list`: a full Public Suffix List file replacing
+    /// `EMBEDDED_SUFFIXES` for `analyze`
+    suffixes: Option<SuffixList>,
+    /// `--url-safe` and `--no-pad`, for `b64encode` and `b64decode`
+    base64: Base64Config,
+    /// `--upper`, `--prefix` and `--separator`, for `hexencode` and
+    /// `hexdecode`
+    hex: HexOptions,
+    /// `--codec`: `encode` and `decode` use this entry of `CODECS` rather
+    /// than percent-encoding
+    codec: Option<String>,
+    /// `--bytes`: `decode` writes raw bytes with no UTF-8 check
+    bytes: bool,
+    /// `--hexdump`: `decode` prints a `hexdump` of the raw bytes
+    hexdump: bool,
+    /// `--chars`: `analyze` also prints the full character histogram
+    chars: bool,
+    /// `--entropy-threshold`, for `analyze`
+    report: ReportOptions,
+    /// `--fail-on-risk`: `analyze` exits with `FAILED_CHECK_EXIT_CODE` if it
+    /// raises a `RiskFlag`; in a batch, that line fails instead
+    fail_on_risk: bool,
+    /// `--max-length`, `--max-host-length`, `--max-label-length` and
+    /// `--max-value-length`, for `analyze`
+    limits: LengthLimits,
+    /// `--fail-on-limit`: `analyze` exits with `FAILED_CHECK_EXIT_CODE` if a limit
+    /// is exceeded; in a batch, that line fails instead
+    fail_on_limit: bool,
+    /// `--summary`: batch `analyze` ends with an `AggregateReport`
+    summary: bool,
+    /// `--per-url`: with `--summary` or `--group-by`, still print each
+    /// line's result
+    per_url: bool,
+    /// `--group-by domain`: batch `analyze` ends with `DomainGroups`
+    group_by_domain: bool,
+    /// `--show-dupes`: batch `dedupe` prints the groups of duplicates
+    /// instead of the unique URLs
+    show_dupes: bool,
+    /// `--by`: what batch `sort` orders by
+    sort_order: SortOrder,
+    /// `--reverse`: batch `sort` sorts descending
+    reverse: bool,
+    /// `--offsets`: `extract` prints the byte offset of each URL
+    offsets: bool,
+    /// `--output` or `-o`, or `--append-output`: results go to this file,
+    /// not stdout; see `Output`
+    output: Option<String>,
+    /// Set by `--append-output`
+    append_output: bool,
+    /// `--null` or `-0`: batch records are NUL-terminated, on input and
+    /// output, instead of lines
+    null: bool,
+    /// `--mmap`: the `--batch` file is memory-mapped; see `mapped`
+    mmap: bool,
+    /// `--quiet` or `-q`: results are discarded unless they go to a file,
+    /// leaving only the exit status
+    quiet: bool,
+    /// `--color`: whether single-input output is colored
+    color: ColorChoice,
+}
+
+impl CliOptions {
+    /// The analysis of `encoder` with `--suffix-list` and the length limits
+    fn analysis(&self, encoder: &URLEncoder) -> URLAnalysis {
+        let suffixes = self.suffixes.as_ref().unwrap_or_else(|| SuffixList::embedded());
+        encoder.analyze_with_limits(suffixes, &self.limits)
+    }
+
+    /// One batch line of `analysis` of `input` in `--format`, or why it
+    /// trips `--fail-on-risk` or `--fail-on-limit`
+    fn analysis_line(
+        &self,
+        encoder: &URLEncoder,
+        input: &str,
+        analysis: &URLAnalysis,
+    ) -> Result<String, String> {
+        if let Some(failure) = self.analysis_failure(analysis) {
+            return Err(failure);
+        }
+        let redactor = self.redactor.as_ref();
+        Ok(match self.format {
+            OutputFormat::Json => encoder.analyze_json_with(analysis, redactor),
+            OutputFormat::Csv => encoder.analyze_csv_row_with(analysis, redactor),
+            OutputFormat::Yaml => {
+                let document = encoder.analyze_yaml_with(analysis, redactor);
+                document.trim_end_matches('\n').to_string()
+            }
+            OutputFormat::Markdown => self.markdown(encoder, analysis),
+            OutputFormat::Html => encoder.analyze_html_row_with(analysis, redactor),
+            OutputFormat::Text => {
+                let shown = match redactor {
+                    Some(redactor) => Cow::Owned(redactor.redact_input(input)),
+                    None => Cow::Borrowed(input),
+                };
+                format!("{}\t{}", shown, analysis.summary_line())
+            }
+        })
+    }
+
+    fn component_options(&self) -> ComponentOptions<'_> {
+        ComponentOptions { redactor: self.redactor.as_ref(), unicode_host: self.display_unicode }
+    }
+
+    /// `--format markdown` output for `analysis` of `encoder`
+    fn markdown(&self, encoder: &URLEncoder, analysis: &URLAnalysis) -> String {
+        encoder.analyze_markdown_with(analysis, &self.report, &self.component_options())
+    }
+
+    /// `FAILED_CHECK_EXIT_CODE` if `analysis` trips `--fail-on-risk` or
+    /// `--fail-on-limit`
+    fn check_analysis(&self, analysis: &URLAnalysis) -> Result<(), ExitCode> {
+        match self.analysis_failure(analysis) {
+            Some(_) => Err(ExitCode(FAILED_CHECK_EXIT_CODE)),
+            None => Ok(()),
+        }
+    }
+
+    /// Why `analysis` trips `--fail-on-risk` or `--fail-on-limit`, if it does
+    fn analysis_failure(&self, analysis: &URLAnalysis) -> Option<String> {
+        if self.fail_on_risk && !analysis.risk_flags.is_empty() {
+            let flags: Vec<String> = analysis.risk_flags.iter().map(|f| f.to_string()).collect();
+            return Some(flags.join("; "));
+        }
+        if self.fail_on_limit && !analysis.limit_violations.is_empty() {
+            return Some(format!("over a length limit: {}", analysis.limit_violations.join("; ")));
+        }
+        None
+    }
+
+    /// Colors for output, which is a terminal only without `--output`
+    fn styler(&self) -> Styler {
+        let is_terminal = self.output.is_none() && io::stdout().is_terminal();
+        Styler::for_output(self.color, is_terminal)
+    }
+
+    /// What ends each batch record: NUL with `--null`, else a newline
+    fn delimiter(&self) -> char {
+        if self.null {
+            '\0'
+        } else {
+            '\n'
+        }
+    }
+
+    /// Where results go: see `Output`
+    fn open_output(&self) -> Output {
+        let Some(path) = &self.output else {
+            return if self.quiet { Output::sink() } else { Output::stdout() };
+        };
+        match Output::open(Path::new(path), self.append_output) {
+            Ok(out) => out,
+            Err(e) => {
+                eprintln!("Error: cannot open {}: {}", path, e);
+                process::exit(USAGE_EXIT_CODE);
+            }
+        }
+    }
+
+    /// Batch output is the `--summary` or `--group-by` result alone,
+    /// without per-line results. An HTML report always has its rows.
+    fn aggregate_only(&self) -> bool {
+        (self.summary || self.group_by_domain) && !self.per_url && self.format != OutputFormat::Html
+    }
+
+    /// An empty aggregate for batch `analyze`
+    fn aggregate(&self) -> AggregateReport {
+        if self.group_by_domain {
+            AggregateReport::with_domains()
+        } else {
+            AggregateReport::default()
+        }
+    }
+
+    /// Options from `flags`; exits with a usage error on a bad value
+    fn parse(flags: &[String]) -> CliOptions {
+        CliOptions::try_parse(flags).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(USAGE_EXIT_CODE);
+        })
+    }
+
+    fn try_parse(flags: &[String]) -> Result<CliOptions, String> {
+        let max_depth = match flag_value(flags, "--max-depth").map(str::parse::<usize>) {
+            Some(Ok(depth)) => depth,
+            Some(Err(_)) => {
+                return Err("Invalid --max-depth: expected a non-negative number".to_string());
+            }
+            None => 5,
+        };
+        let jobs = match flag_value(flags, "--jobs").map(str::parse::<usize>) {
+            Some(Ok(jobs)) if jobs > 0 => jobs,
+            Some(_) => {
+                return Err("Invalid --jobs: expected a positive number".to_string());
+            }
+            None => 1,
+        };
+        let policy = match flag_value(flags, "--on-conflict") {
+            Some(name) => match MergePolicy::from_name(name) {
+                Some(policy) => policy,
+                None => {
+                    return Err(format!(
+                        "Unknown --on-conflict: {}. Use 'keep', 'overwrite', or 'append'",
+                        name
+                    ));
+                }
+            },
+            None => MergePolicy::KeepExisting,
+        };
+        let hash = match flag_value(flags, "--hash") {
+            Some(name) => match CacheKeyHash::from_name(name) {
+                Some(hash) => hash,
+                None => {
+                    return Err(format!("Unknown --hash: {}. Use 'none', 'fnv', or 'sha256'", name));
+                }
+            },
+            None => CacheKeyHash::None,
+        };
+        let format = match flag_value(flags, "--format") {
+            Some(name) => match OutputFormat::from_name(name) {
+                Some(format) => format,
+                None => {
+                    return Err(format!(
+                        "Unknown --format: {}. \
+                         Use 'text', 'json', 'csv', 'markdown', 'html', or 'yaml'",
+                        name
+                    ));
+                }
+            },
+            None if flags.iter().any(|f| f == "--json") => OutputFormat::Json,
+            None if flags.iter().any(|f| f == "--csv") => OutputFormat::Csv,
+            None => OutputFormat::Text,
+        };
+        let sort_order = match flag_value(flags, "--by") {
+            Some(name) => match SortOrder::from_name(name) {
+                Some(order) => order,
+                None => {
+                    return Err(format!(
+                        "Unknown --by: {}. Use 'hierarchy', 'length', or 'alpha'",
+                        name
+                    ));
+                }
+            },
+            None => SortOrder::Hierarchy,
+        };
+        let suffixes = match flag_value(flags, "--suffix-list") {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(text) => Some(SuffixList::parse(&text)),
+                Err(e) => return Err(format!("cannot read {}: {}", path, e)),
+            },
+            None => None,
+        };
+        let report = match flag_value(flags, "--entropy-threshold").map(str::parse::<f64>) {
+            Some(Ok(threshold)) if threshold >= 0.0 => {
+                ReportOptions { entropy_threshold: threshold }
+            }
+            Some(_) => {
+                return Err(
+                    "Invalid --entropy-threshold: expected a non-negative number".to_string()
+                );
+            }
+            None => ReportOptions::default(),
+        };
+        let limit = |name: &str, default: usize| match flag_value(flags, name).map(str::parse) {
+            Some(Ok(limit)) => Ok(limit),
+            Some(Err(_)) => Err(format!("Invalid {}: expected a non-negative number", name)),
+            None => Ok(default),
+        };
+        let defaults = LengthLimits::default();
+        let limits = LengthLimits {
+            url: limit("--max-length", defaults.url)?,
+            host: limit("--max-host-length", defaults.host)?,
+            label: limit("--max-label-length", defaults.label)?,
+            query_value: limit("--max-value-length", defaults.query_value)?,
+        };
+        let group_by_domain = match flag_value(flags, "--group-by") {
+            Some("domain") => true,
+            Some(name) => {
+                return Err(format!("Unknown --group-by: {}. Use 'domain'", name));
+            }
+            None => false,
+        };
+        let codec = match flag_value(flags, "--codec") {
+            Some(name) if CODECS.contains(&name) => Some(name.to_string()),
+            Some(name) => {
+                let codecs = CODECS.join(", ");
+                return Err(format!("Unknown --codec: {}. Use one of: {}", name, codecs));
+            }
+            None => None,
+        };
+        let key = flag_value(flags, "--key").unwrap_or("");
+        let mut anonymizer = flag_values(flags, "--keep-params")
+            .into_iter()
+            .flat_map(|names| names.split(','))
+            .fold(Anonymizer::new(key), Anonymizer::with_kept_param);
+        match flag_value(flags, "--path-depth").map(str::parse::<usize>) {
+            Some(Ok(depth)) => anonymizer = anonymizer.with_path_depth(depth),
+            Some(Err(_)) => {
+                return Err("Invalid --path-depth: expected a non-negative number".to_string());
+            }
+            None => {}
+        }
+        if flags.iter().any(|f| f == "--hash-host") {
+            anonymizer = anonymizer.with_hashed_host();
+        }
+        let redact_params = flag_values(flags, "--redact-param");
+        let redactor = (flags.iter().any(|f| f == "--redact") || !redact_params.is_empty())
+            .then(|| redact_params.into_iter().fold(Redactor::default(), Redactor::with_param));
+        let component = match flag_value(flags, "--component") {
+            Some(name) => match Component::from_name(name) {
+                Some(component) => Some(component),
+                None => {
+                    return Err(format!(
+                        "Unknown component: {}. Use 'path', 'segment', 'query', 'fragment', or 'userinfo'",
+                        name
+                    ));
+                }
+            },
+            None => None,
+        };
+        let mode = if flags.iter().any(|f| f == "--form") {
+            Some(EncodeMode::Form)
+        } else if flags.iter().any(|f| f == "--rfc3986") {
+            Some(EncodeMode::Rfc3986)
+        } else {
+            None
+        };
+        let safe = flag_value(flags, "--safe").unwrap_or("");
+        let not_safe = flag_value(flags, "--unsafe").unwrap_or("");
+        let encode_set = not_safe.bytes().fold(
+            safe.bytes().fold(EncodeSet::default(), EncodeSet::add),
+            EncodeSet::remove,
+        );
+
+        let quiet = flags.iter().any(|f| f == "--quiet");
+        let verbosity = if quiet {
+            0
+        } else {
+            flags.iter().filter(|f| *f == "--verbose").count().min(2) as u8
+        };
+
+        Ok(CliOptions {
+            mode,
+            encode_set,
+            component,
+            idempotent: flags.iter().any(|f| f == "--no-double-encode"),
+            decode: DecodeOptions {
+                lenient: flags.iter().any(|f| f == "--lenient"),
+                verbosity,
+            },
+            lossy: flags.iter().any(|f| f == "--lossy"),
+            recursive: flags.iter().any(|f| f == "--recursive"),
+            max_depth,
+            jobs,
+            format,
+            csv_header: !flags.iter().any(|f| f == "--no-header"),
+            group: flags.iter().any(|f| f == "--group"),
+            edits: query_edits(flags, policy),
+            rules: rewrite_rules(flags)?,
+            keep: flag_values(flags, "--keep").into_iter().map(str::to_string).collect(),
+            extra: flag_values(flags, "--extra").into_iter().map(str::to_string).collect(),
+            redactor,
+            anonymizer,
+            normalize: NormalizeOptions {
+                sort_params: flags.iter().any(|f| f == "--sort-params"),
+                decode_dots: flags.iter().any(|f| f == "--decode-dots"),
+                idna: flags.iter().any(|f| f == "--idna"),
+                keep_escapes: flags.iter().any(|f| f == "--keep-escapes"),
+                keep_default_port: flags.iter().any(|f| f == "--keep-default-port"),
+                keep_dot_segments: flags.iter().any(|f| f == "--keep-dot-segments"),
+                strip_fragment: flags.iter().any(|f| f == "--strip-fragment"),
+                verbosity,
+            },
+            cache_key: CacheKeyOptions {
+                keep_params: flag_values(flags, "--keep").into_iter().map(str::to_string).collect(),
+                strip_params: flag_values(flags, "--extra")
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+                keep_param_order: flags.iter().any(|f| f == "--keep-param-order"),
+                keep_fragment: flags.iter().any(|f| f == "--keep-fragment"),
+                decode_dots: flags.iter().any(|f| f == "--decode-dots"),
+                idna: flags.iter().any(|f| f == "--idna"),
+                hash,
+            },
+            escapes_only: flags.iter().any(|f| f == "--normalize-escapes"),
+            idna: flags.iter().any(|f| f == "--idna"),
+            display_unicode: flags.iter().any(|f| f == "--display-unicode"),
+            suffixes,
+            base64: Base64Config {
+                url_safe: flags.iter().any(|f| f == "--url-safe"),
+                pad: !flags.iter().any(|f| f == "--no-pad"),
+            },
+            hex: HexOptions {
+                upper: flags.iter().any(|f| f == "--upper"),
+                prefix: flag_value(flags, "--prefix").unwrap_or_default().to_string(),
+                separator: flag_value(flags, "--separator").unwrap_or_default().to_string(),
+            },
+            codec,
+            encode_options: EncodeOptions {
+                hex_case: if flags.iter().any(|f| f == "--lower-hex") {
+                    HexCase::Lower
+                } else {
+                    HexCase::Upper
+                },
+                verbosity,
+            },
+            url: flags.iter().any(|f| f == "--url"),
+            bytes: flags.iter().any(|f| f == "--bytes"),
+            hexdump: flags.iter().any(|f| f == "--hexdump"),
+            chars: flags.iter().any(|f| f == "--chars"),
+            report,
+            fail_on_risk: flags.iter().any(|f| f == "--fail-on-risk"),
+            limits,
+            fail_on_limit: flags.iter().any(|f| f == "--fail-on-limit"),
+            summary: flags.iter().any(|f| f == "--summary"),
+            per_url: flags.iter().any(|f| f == "--per-url"),
+            group_by_domain,
+            show_dupes: flags.iter().any(|f| f == "--show-dupes"),
+            sort_order,
+            reverse: flags.iter().any(|f| f == "--reverse"),
+            offsets: flags.iter().any(|f| f == "--offsets"),
+            output: flag_value(flags, "--output")
+                .or_else(|| flag_value(flags, "--append-output"))
+                .map(str::to_string),
+            append_output: flag_value(flags, "--append-output").is_some(),
+            null: flags.iter().any(|f| f == "--null"),
+            mmap: flags.iter().any(|f| f == "--mmap"),
+            quiet,
+            color: color_choice(flags)?,
+        })
+    }
+
+    /// The codec `operation` runs, and whether it decodes: `encode` and
+    /// `decode` with `--codec`, and the `b64*`, `hex*` and `html-*`
+    /// operations
+    fn codec_for(&self, operation: &str) -> Option<(Box<dyn Codec>, bool)> {
+        let base64 = if self.base64.url_safe { "base64url" } else { "base64" };
+        let (name, decode) = match (operation, &self.codec) {
+            ("encode" | "decode", Some(name)) => (name.as_str(), operation == "decode"),
+            ("b64encode" | "b64decode", _) => (base64, operation == "b64decode"),
+            ("hexencode" | "hexdecode", _) => ("hex", operation == "hexdecode"),
+            ("html-encode" | "html-decode", _) => ("html", operation == "html-decode"),
+            _ => return None,
+        };
+        let options = CodecOptions {
+            encode_set: self.encode_set,
+            hex_case: self.encode_options.hex_case,
+            base64: self.base64,
+            hex: self.hex.clone(),
+        };
+        codec_with(name, &options).map(|codec| (codec, decode))
+    }
+
+    fn encoder(&self, input: &str) -> URLEncoder {
+        let encoder = URLEncoder::new(input.to_string())
+            .with_encode_set(self.encode_set)
+            .with_encode_options(self.encode_options);
+        match self.mode {
+            Some(mode) => encoder.with_mode(mode),
+            None => encoder,
+        }
+    }
+
+    /// The input URL with tracking parameters stripped per `--keep`/`--extra`
+    fn clean(&self, input: &str) -> Result<String, ParseError> {
+        let mut url = URLEncoder::new(input.to_string()).parse()?;
+        let keep: Vec<&str> = self.keep.iter().map(String::as_str).collect();
+        let extra: Vec<&str> = self.extra.iter().map(String::as_str).collect();
+
+        url.strip_tracking_params(&keep, &extra);
+        Ok(url.to_string())
+    }
+
+    fn stream_options(&self) -> StreamOptions {
+        StreamOptions {
+            mode: self.mode,
+            encode_set: self.encode_set,
+            hex_case: self.encode_options.hex_case,
+            decode: self.decode,
+            lossy: self.lossy,
+            raw_bytes: self.bytes,
+        }
+    }
+}
+
+/// Run `operation` on one input and print the result. Failures are reported
+/// on stderr and returned as the exit code to use.
+fn run_operation(
+    input: &str,
+    operation: &str,
+    options: &CliOptions,
+    out: &mut dyn Write,
+) -> Result<(), ExitCode> {
+    let encoder = options.encoder(input);
+
+    if let Some((codec, decode)) = options.codec_for(operation) {
+        if !decode {
+            writeln!(out, "{}", codec.encode(input.as_bytes()))?;
+            return Ok(());
+        }
+        return match codec.decode(input) {
+            Ok(bytes) => Ok(out.write_all(&bytes)?),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                eprintln!("{}", e.pointer(input));
+                Err(ExitCode(PARSE_EXIT_CODE))
+            }
+        };
+    }
+
+    match operation {
+        "encode" if options.idna => match encoder.ascii_host() {
+            Ok(encoded) => writeln!(out, "Encoded: {}", encoded)?,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Err(ExitCode(PARSE_EXIT_CODE));
+            }
+        },
+        "encode" => {
+            let encoded = match options.component {
+                Some(component) => encoder.encode_component(component),
+                None if options.url => encoder.encode_url(),
+                None if options.idempotent => encoder.encode_idempotent(),
+                None => encoder.encode(),
+            };
+            writeln!(out, "Encoded: {}", options.styler().changes(input, &encoded))?;
+        }
+        "decode" if options.bytes || options.hexdump => {
+            match encoder.decode_bytes_with(&options.decode) {
+                Ok(bytes) if options.hexdump => write!(out, "{}", hexdump(&bytes))?,
+                Ok(bytes) => out.write_all(&bytes)?,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    eprintln!("{}", e.pointer(input));
+                    return Err(ExitCode(PARSE_EXIT_CODE));
+                }
+            }
+        }
+        "decode" if options.recursive => {
+            match encoder.decode_recursive_with(options.max_depth, &options.decode) {
+                Ok((decoded, passes)) => {
+                    writeln!(out, "Decoded: {}", decoded)?;
+                    writeln!(out, "Passes: {}", passes)?;
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    eprintln!("{}", e.pointer(input));
+                    return Err(ExitCode(PARSE_EXIT_CODE));
+                }
+            }
+        }
+        "decode" if options.lossy => {
+            writeln!(out, "Decoded: {}", encoder.decode_lossy_with(&options.decode))?;
+        }
+        "decode" => {
+            match encoder.decode_with(&options.decode) {
+                Ok(decoded) => writeln!(out, "Decoded: {}", decoded)?,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    eprintln!("{}", e.pointer(input));
+                    return Err(ExitCode(PARSE_EXIT_CODE));
+                }
+            }
+        }
+        "analyze" if options.format == OutputFormat::Json => {
+            let analysis = options.analysis(&encoder);
+            writeln!(out, "{}", encoder.analyze_json_with(&analysis, options.redactor.as_ref()))?;
+            options.check_analysis(&analysis)?;
+        }
+        "analyze" if options.format == OutputFormat::Yaml => {
+            let analysis = options.analysis(&encoder);
+            write!(out, "{}", encoder.analyze_yaml_with(&analysis, options.redactor.as_ref()))?;
+            options.check_analysis(&analysis)?;
+        }
+        "analyze" if options.format == OutputFormat::Csv => {
+            let analysis = options.analysis(&encoder);
+            if options.csv_header {
+                writeln!(out, "{}", ANALYZE_CSV_HEADER)?;
+            }
+            let row = encoder.analyze_csv_row_with(&analysis, options.redactor.as_ref());
+            writeln!(out, "{}", row)?;
+            options.check_analysis(&analysis)?;
+        }
+        "analyze" if options.format == OutputFormat::Html => {
+            let analysis = options.analysis(&encoder);
+            let mut aggregate = AggregateReport::default();
+            aggregate.add(&encoder, &analysis);
+            let row = encoder.analyze_html_row_with(&analysis, options.redactor.as_ref());
+            write!(out, "{}", html_report(&aggregate, &[row]))?;
+            options.check_analysis(&analysis)?;
+        }
+        "analyze" if options.format == OutputFormat::Markdown => {
+            let analysis = options.analysis(&encoder);
+            write!(out, "{}", options.markdown(&encoder, &analysis))?;
+            options.check_analysis(&analysis)?;
+        }
+        "analyze" => {
+            let styler = options.styler();
+            let analysis = options.analysis(&encoder);
+            writeln!(out, "{}", styler.report(&analysis.report_with(&options.report)))?;
+            if options.chars {
+                writeln!(out, "{}", styler.report("Character Histogram:"))?;
+                for line in analysis.chars.histogram_lines() {
+                    writeln!(out, "{}", line)?;
+                }
+                writeln!(out)?;
+            }
+
+            let double_encoded = encoder.double_encoded_positions();
+            if !double_encoded.is_empty() {
+                let offsets: Vec<String> = double_encoded.iter().map(|p| p.to_string()).collect();
+                writeln!(out, "Double-encoded sequences at byte offsets: {}", offsets.join(", "))?;
+            }
+
+            let components = encoder.extract_components_with(&options.component_options());
+            writeln!(out, "\n{}", styler.report("URL Components:"))?;
+            for component in components {
+                writeln!(out, "  {}", styler.field(&component))?;
+            }
+            options.check_analysis(&analysis)?;
+        }
+        "rewrite" | "clean" | "normalize" | "anonymize" => {
+            let result = match operation {
+                "rewrite" => encoder.rewrite(&options.rules, &options.edits),
+                "clean" => options.clean(input),
+                "anonymize" => encoder.anonymize(&options.anonymizer),
+                _ if options.escapes_only => Ok(encoder.normalize_escapes()),
+                _ => encoder.normalize_with(&options.normalize),
+            };
+            match result {
+                Ok(url) => writeln!(out, "{}", url)?,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return Err(ExitCode(PARSE_EXIT_CODE));
+                }
+            }
+        }
+        "recase-escapes" => {
+            writeln!(out, "{}", recase_escapes(input, options.encode_options.hex_case))?
+        }
+        // A single URL is never a duplicate
+        "dedupe" if options.show_dupes => {}
+        "dedupe" | "sort" => writeln!(out, "{}", input)?,
+        "cache-key" => writeln!(out, "{}", encoder.cache_key(&options.cache_key))?,
+        "extract" => {
+            for line in extracted_lines(input, options.offsets) {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        "to-file-url" => writeln!(out, "{}", file_url_for_input(input))?,
+        "from-file-url" => match file_url_to_path(input) {
+            Ok(path) => writeln!(out, "{}", path.display())?,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Err(ExitCode(PARSE_EXIT_CODE));
+            }
+        },
+        "data-decode" => match DataUrl::parse(input) {
+            Ok(data) => out.write_all(&data.payload_bytes)?,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Err(ExitCode(PARSE_EXIT_CODE));
+            }
+        },
+        "validate" => match encoder.validate() {
+            Ok(notes) => {
+                writeln!(out, "Valid")?;
+                for note in notes {
+                    writeln!(out, "Note: {}", note)?;
+                }
+            }
+            Err(e) => {
+                eprintln!("Invalid: {}", e);
+                return Err(ExitCode(FAILED_CHECK_EXIT_CODE));
+            }
+        },
+        "check" => {
+            let report = encoder.verify_roundtrip();
+            for line in report.lines() {
+                writeln!(out, "{}", line)?;
+            }
+            if !report.is_ok() {
+                return Err(ExitCode(FAILED_CHECK_EXIT_CODE));
+            }
+        }
+        "params" => {
+            let lines = if options.group {
+                grouped_params_lines(&encoder.query_map())
+            } else {
+                params_lines(&encoder.parse_query())
+            };
+            let styler = options.styler();
+            for line in lines {
+                writeln!(out, "{}", styler.param_line(&line))?;
+            }
+        }
+        _ => {
+            eprintln!("{}", unknown_operation(operation));
+            return Err(ExitCode(USAGE_EXIT_CODE));
+        }
+    }
+
+    Ok(())
+}
+
+/// Exit status for a usage error, such as an unknown operation or flag
+/// value or a file that cannot be opened, and for output that cannot be
+/// written
+const USAGE_EXIT_CODE: i32 = 1;
+
+/// Exit status when the input does not decode or parse
+const PARSE_EXIT_CODE: i32 = 2;
+
+/// Exit status when a check fails: `validate` of an invalid URL, `check`
+/// of an input that does not round-trip, or `analyze` with
+/// `--fail-on-risk` or `--fail-on-limit`
+const FAILED_CHECK_EXIT_CODE: i32 = 3;
+
+/// Exit status of batch mode when any line failed
+const PARTIAL_EXIT_CODE: i32 = 4;
+
+/// The exit statuses above, as `--help` lists them
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  success
+  1  usage error: unknown operation or flag value, unreadable input or unwritable output
+  2  the input does not decode or parse
+  3  a check failed: validate, check, or analyze with --fail-on-risk or --fail-on-limit
+  4  batch mode: at least one line failed
+equiv and diff exit 0 if the URLs match, 1 if they differ and 2 on error.";
+
+/// Command-line synopsis, printed by `--help` and for a usage error
+const USAGE: &str = "\
+Usage: url_encoder <operation> [flags] [--] <url_or_text|->
+       url_encoder <url_or_text|-> [<operation>] [flags]
+       url_encoder <command> [args]... [flags]";
+
+/// ExitCode is how a failed operation exits; its message is already on
+/// stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ExitCode(i32);
+
+/// Results that cannot be written are reported here, exiting with
+/// `USAGE_EXIT_CODE`
+impl From<io::Error> for ExitCode {
+    fn from(e: io::Error) -> Self {
+        eprintln!("Error: cannot write output: {}", e);
+        ExitCode(USAGE_EXIT_CODE)
+    }
+}
+
+/// Output is where results go: stdout, the `--output` file, or nowhere
+/// with `--quiet`
+///
+/// The file is written under a temporary name beside it and renamed into
+/// place by `finish`, so a failed or interrupted run leaves any earlier
+/// file as it was; dropping an unfinished Output removes the temporary
+/// file. With `--append-output` results are added to the end of the file
+/// as they are written.
+struct Output {
+    writer: BufWriter<OutputTarget>,
+    /// The temporary file and the path it replaces, until `finish`
+    rename: Option<(PathBuf, PathBuf)>,
+}
+
+enum OutputTarget {
+    Stdout(io::Stdout),
+    File(File),
+    Sink(io::Sink),
+}
+
+impl Write for OutputTarget {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputTarget::Stdout(stdout) => stdout.write(buf),
+            OutputTarget::File(file) => file.write(buf),
+            OutputTarget::Sink(sink) => sink.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputTarget::Stdout(stdout) => stdout.flush(),
+            OutputTarget::File(file) => file.flush(),
+            OutputTarget::Sink(sink) => sink.flush(),
+        }
+    }
+}
+
+impl Output {
+    fn stdout() -> Output {
+        Output { writer: BufWriter::new(OutputTarget::Stdout(io::stdout())), rename: None }
+    }
+
+    /// Discard everything written
+    fn sink() -> Output {
+        Output { writer: BufWriter::new(OutputTarget::Sink(io::sink())), rename: None }
+    }
+
+    /// Write to `path`, replacing it on `finish` or, with `append`, adding
+    /// to it
+    fn open(path: &Path, append: bool) -> io::Result<Output> {
+        if append {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+            return Ok(Output { writer: BufWriter::new(OutputTarget::File(file)), rename: None });
+        }
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "output path has no file name")
+        })?;
+        let temp = path.with_file_name(format!(
+            ".{}.{}.tmp",
+            name.to_string_lossy(),
+            process::id()
+        ));
+        let file = File::create(&temp)?;
+        Ok(Output {
+            writer: BufWriter::new(OutputTarget::File(file)),
+            rename: Some((temp, path.to_path_buf())),
+        })
+    }
+
+    /// Flush everything written and move a replacement file into place
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if let Some((temp, path)) = &self.rename {
+            if let OutputTarget::File(file) = self.writer.get_ref() {
+                file.sync_all()?;
+            }
+            std::fs::rename(temp, path)?;
+            self.rename = None;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some((temp, _)) = &self.rename {
+            let _ = std::fs::remove_file(temp);
+        }
+    }
+}
+
+/// Whether a run that ended in `result` wrote a complete result
+///
+/// `FAILED_CHECK_EXIT_CODE` is a verdict on a complete result; other
+/// failures may have stopped midway.
+fn is_complete(result: &Result<(), ExitCode>) -> bool {
+    matches!(result, Ok(()) | Err(ExitCode(FAILED_CHECK_EXIT_CODE)))
+}
+
+/// Keep `out` if the run was complete, then exit with its code
+///
+/// An incomplete run drops `out`, leaving a replaced output file as it was.
+fn conclude(result: Result<(), ExitCode>, mut out: Output) {
+    let result = if is_complete(&result) {
+        out.finish().map_err(ExitCode::from).and(result)
+    } else {
+        result
+    };
+    if let Err(ExitCode(code)) = result {
+        drop(out);
+        process::exit(code);
+    }
+}
+
+/// Result of `operation` on one batch line, as the single line to print
+fn batch_line(input: &str, operation: &str, options: &CliOptions) -> Result<String, String> {
+    let encoder = options.encoder(input);
+
+    if let Some((codec, decode)) = options.codec_for(operation) {
+        if !decode {
+            return Ok(codec.encode(input.as_bytes()));
+        }
+        let bytes = codec.decode(input).map_err(|e| e.to_string())?;
+        return String::from_utf8(bytes)
+            .map_err(|_| "output is not UTF-8; decode it on its own for raw bytes".to_string());
+    }
+
+    match operation {
+        "encode" if options.idna => encoder.ascii_host().map_err(|e| e.to_string()),
+        "encode" => Ok(match options.component {
+            Some(component) => encoder.encode_component(component),
+            None if options.url => encoder.encode_url(),
+            None if options.idempotent => encoder.encode_idempotent(),
+            None => encoder.encode(),
+        }),
+        "decode" if options.bytes || options.hexdump => {
+            Err("--bytes and --hexdump need a single input; use --raw for piped data".to_string())
+        }
+        "decode" if options.recursive => encoder
+            .decode_recursive_with(options.max_depth, &options.decode)
+            .map(|(decoded, _)| decoded)
+            .map_err(|e| e.to_string()),
+        "decode" if options.lossy => Ok(encoder.decode_lossy_with(&options.decode)),
+        "decode" => encoder.decode_with(&options.decode).map_err(|e| e.to_string()),
+        "analyze" => options.analysis_line(&encoder, input, &options.analysis(&encoder)),
+        "rewrite" => {
+            encoder.rewrite(&options.rules, &options.edits).map_err(|e| e.to_string())
+        }
+        "clean" => options.clean(input).map_err(|e| e.to_string()),
+        "normalize" if options.escapes_only => Ok(encoder.normalize_escapes()),
+        "normalize" => encoder.normalize_with(&options.normalize).map_err(|e| e.to_string()),
+        "dedupe" => Ok(dedupe_key(input, &options.normalize)),
+        "cache-key" => Ok(encoder.cache_key(&options.cache_key)),
+        "anonymize" => encoder.anonymize(&options.anonymizer).map_err(|e| e.to_string()),
+        "sort" => Ok(input.to_string()),
+        "extract" => {
+            let urls = extracted_lines(input, options.offsets);
+            Ok(urls.join(&options.delimiter().to_string()))
+        }
+        "recase-escapes" => Ok(recase_escapes(input, options.encode_options.hex_case).into_owned()),
+        "to-file-url" => Ok(file_url_for_input(input)),
+        "from-file-url" => match file_url_to_path(input) {
+            Ok(path) => Ok(path.display().to_string()),
+            Err(e) => Err(e.to_string()),
+        },
+        "data-decode" => {
+            let data = DataUrl::parse(input).map_err(|e| e.to_string())?;
+            String::from_utf8(data.payload_bytes)
+                .map_err(|_| "payload is not UTF-8; decode it on its own for raw bytes".to_string())
+        }
+        "validate" => match encoder.validate() {
+            Ok(notes) if notes.is_empty() => Ok("valid".to_string()),
+            Ok(notes) => Ok(format!("valid\t{}", notes.join("\t"))),
+            Err(e) => Err(e.to_string()),
+        },
+        "check" => {
+            let report = encoder.verify_roundtrip();
+            let lines = report.lines().join("\t");
+            if report.is_ok() {
+                Ok(lines)
+            } else {
+                Err(lines)
+            }
+        }
+        // Tab-separated so each input still produces one output line
+        "params" if options.group => Ok(grouped_params_lines(&encoder.query_map()).join("\t")),
+        "params" => Ok(params_lines(&encoder.parse_query()).join("\t")),
+        _ => Err(unknown_operation(operation)),
+    }
+}
+
+/// Counts reported at the end of a batch run
+#[derive(Debug, Default, PartialEq, Eq)]
+struct BatchSummary {
+    processed: usize,
+    failed: usize,
+    /// Analyzed lines of each `ReferenceKind`, indexed by discriminant
+    references: [usize; 5],
+}
+
+/// Lines read per worker before a batch block is processed
+const BATCH_LINES_PER_JOB: usize = 4096;
+
+/// Batch `dedupe` state: the normalized forms seen so far and, for
+/// `--show-dupes`, every input grouped by normalized form
+#[derive(Debug, Default)]
+struct Dedupe {
+    seen: HashSet<String>,
+    /// Index into `groups` by normalized form, only with `--show-dupes`
+    index: HashMap<String, usize>,
+    groups: Vec<Vec<String>>,
+}
+
+impl Dedupe {
+    /// Whether `key` is new, recording `line` under it when grouping
+    fn add(&mut self, key: String, line: &str, group: bool) -> bool {
+        if !group {
+            return self.seen.insert(key);
+        }
+        match self.index.get(&key) {
+            Some(&i) => {
+                self.groups[i].push(line.to_string());
+                false
+            }
+            None => {
+                self.index.insert(key, self.groups.len());
+                self.groups.push(vec![line.to_string()]);
+                true
+            }
+        }
+    }
+
+    /// Each group with duplicates: its first input, then the others
+    /// indented
+    fn duplicate_groups(&self) -> String {
+        let mut out = String::new();
+        for group in self.groups.iter().filter(|group| group.len() > 1) {
+            out.push_str(&group[0]);
+            out.push('\n');
+            for duplicate in &group[1..] {
+                out.push_str(&format!("  {}\n", duplicate));
+            }
+        }
+        out
+    }
+}
+
+/// Stable sort of `urls` by key, descending with `reverse`
+///
+/// URLs without a key come last in their input order, even with `reverse`.
+/// Those are the lines `SortKey::new` cannot parse, schemeless ones such as
+/// `example.com/a` included.
+fn sort_urls(urls: &mut [(Option<SortKey>, String)], reverse: bool) {
+    urls.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) if reverse => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    });
+}
+
+/// Run `operation` on every line of `reader`, one result per line on `out`
+///
+/// Empty and `#` lines are skipped; a failing line goes to `err` with its
+/// number. `options.null` splits and ends records on NUL instead, skipping
+/// only empty ones, and `options.jobs` runs lines on threads in input order.
+fn run_batch<R: BufRead, W: Write, E: Write>(
+    reader: R,
+    out: W,
+    err: E,
+    operation: &str,
+    options: &CliOptions,
+) -> io::Result<BatchSummary> {
+    let records = reader.split(options.delimiter() as u8).map(|record| record.map(Cow::Owned));
+    run_batch_records(records, out, err, operation, options)
+}
+
+/// `run_batch` over records already split at `options.delimiter()`
+///
+/// Borrowed records, as `--mmap` gives, are only copied where an
+/// operation needs its own string.
+fn run_batch_records<'a, I, W, E>(
+    records: I,
+    mut out: W,
+    mut err: E,
+    operation: &str,
+    options: &CliOptions,
+) -> io::Result<BatchSummary>
+where
+    I: Iterator<Item = io::Result<Cow<'a, [u8]>>>,
+    W: Write,
+    E: Write,
+{
+    let mut summary = BatchSummary::default();
+    let mut aggregate = options.aggregate();
+    let mut dedupe = Dedupe::default();
+    let mut sorted = Vec::new();
+    let mut html_rows = Vec::new();
+    let suffixes = options.suffixes.as_ref().unwrap_or_else(|| SuffixList::embedded());
+    let block_size = BATCH_LINES_PER_JOB * options.jobs;
+    let mut block = Vec::with_capacity(block_size);
+    let end = options.delimiter();
+    let mut lines = records.enumerate();
+
+    if operation == "analyze" && options.format == OutputFormat::Csv && options.csv_header {
+        write!(out, "{}{}", ANALYZE_CSV_HEADER, end)?;
+    }
+
+    loop {
+        block.clear();
+        for (index, line) in lines.by_ref() {
+            let mut line = line?;
+            if options.null {
+                if line.is_empty() {
+                    continue;
+                }
+            } else {
+                if line.last() == Some(&b'\r') {
+                    match &mut line {
+                        Cow::Borrowed(record) => *record = &record[..record.len() - 1],
+                        Cow::Owned(record) => {
+                            record.pop();
+                        }
+                    }
+                }
+                if line.is_empty() || line.starts_with(b"#") {
+                    continue;
+                }
+            }
+            block.push((index + 1, line));
+            if block.len() == block_size {
+                break;
+            }
+        }
+        if block.is_empty() {
+            break;
+        }
+
+        let (results, block_aggregate) = batch_block(&block, operation, options);
+        aggregate.merge(&block_aggregate);
+        for ((number, line), (result, kind)) in block.iter().zip(results) {
+            summary.processed += 1;
+            if let Some(kind) = kind {
+                summary.references[kind as usize] += 1;
+            }
+            match result {
+                Ok(_) if options.aggregate_only() => {}
+                Ok(key) if operation == "dedupe" => {
+                    let line = String::from_utf8_lossy(line);
+                    if dedupe.add(key, &line, options.show_dupes) && !options.show_dupes {
+                        write!(out, "{}{}", line, end)?;
+                    }
+                }
+                Ok(line) if operation == "sort" => {
+                    let key = SortKey::new(&line, options.sort_order, suffixes);
+                    sorted.push((key, line));
+                }
+                Ok(urls) if operation == "extract" && urls.is_empty() => {}
+                Ok(row) if operation == "analyze" && options.format == OutputFormat::Html => {
+                    html_rows.push(row)
+                }
+                Ok(output) => write!(out, "{}{}", output, end)?,
+                Err(message) => {
+                    summary.failed += 1;
+                    writeln!(err, "line {}: Error: {}", number, message)?;
+                }
+            }
+        }
+    }
+
+    if operation == "sort" {
+        sort_urls(&mut sorted, options.reverse);
+        for (_, line) in &sorted {
+            write!(out, "{}{}", line, end)?;
+        }
+    }
+    if operation == "dedupe" && options.show_dupes {
+        write!(out, "{}", dedupe.duplicate_groups())?;
+    }
+    if operation == "analyze" && options.format == OutputFormat::Html {
+        write!(out, "{}", html_report(&aggregate, &html_rows))?;
+    } else if operation == "analyze" {
+        if let Some(domains) = &aggregate.domains {
+            if options.format == OutputFormat::Json {
+                write!(out, "{}", domains.json_lines())?;
+            } else {
+                write!(out, "{}", domains.table())?;
+            }
+        }
+        if options.summary {
+            write!(out, "{}", aggregate.report())?;
+        }
+    }
+    out.flush()?;
+    Ok(summary)
+}
+
+/// One batch line's result and, for `analyze`, its `ReferenceKind`
+type LineResult = (Result<String, String>, Option<ReferenceKind>);
+
+/// Results for one block of numbered lines, in block order, and with
+/// `--summary` or `--group-by` the aggregate of the block
+fn batch_block(
+    block: &[(usize, Cow<[u8]>)],
+    operation: &str,
+    options: &CliOptions,
+) -> (Vec<LineResult>, AggregateReport) {
+    let aggregate = operation == "analyze"
+        && (options.summary || options.group_by_domain || options.format == OutputFormat::Html);
+    let process = |lines: &[(usize, Cow<[u8]>)]| {
+        let mut report = options.aggregate();
+        let results = lines
+            .iter()
+            .map(|(_, line)| {
+                let line = match std::str::from_utf8(line) {
+                    Ok(line) => line,
+                    Err(e) => return (Err(format!("input is not valid UTF-8: {}", e)), None),
+                };
+                if operation != "analyze" {
+                    return (batch_line(line, operation, options), None);
+                }
+                // One analysis serves the aggregate and, with --per-url, the line.
+                let encoder = options.encoder(line);
+                let analysis = options.analysis(&encoder);
+                if aggregate {
+                    report.add(&encoder, &analysis);
+                }
+                let output = if options.aggregate_only() {
+                    options.analysis_failure(&analysis).map_or(Ok(String::new()), Err)
+                } else {
+                    options.analysis_line(&encoder, line, &analysis)
+                };
+                (output, encoder.reference_kind())
+            })
+            .collect();
+        (results, report)
+    };
+
+    if options.jobs <= 1 || block.len() < 2 {
+        return process(block);
+    }
+
+    // Never more workers than lines; chunk results are joined in order.
+    let chunk_size = block.len().div_ceil(options.jobs);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = block
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || process(chunk)))
+            .collect();
+        let mut results = Vec::with_capacity(block.len());
+        let mut aggregate = options.aggregate();
+        for worker in workers {
+            let (chunk_results, chunk_aggregate) = worker.join().expect("batch worker panicked");
+            results.extend(chunk_results);
+            aggregate.merge(&chunk_aggregate);
+        }
+        (results, aggregate)
+    })
+}
+
+/// The output of `extract`: one URL per line, after its offset and a tab
+/// with `offsets`
+fn extracted_lines(text: &str, offsets: bool) -> Vec<String> {
+    extract_urls(text)
+        .into_iter()
+        .map(|(offset, url)| if offsets { format!("{}\t{}", offset, url) } else { url })
+        .collect()
+}
+
+/// Batch-process `reader` to stdout and report the totals on stderr,
+/// exiting with `PARTIAL_EXIT_CODE` if any line failed
+///
+/// `--quiet` leaves out the totals; failed lines are still reported.
+fn batch_main<R: BufRead>(reader: R, operation: &str, options: &CliOptions, mut out: Output) {
+    let result = run_batch(reader, &mut out, io::stderr().lock(), operation, options);
+    conclude_batch(result, operation, options, out);
+}
+
+/// `batch_main` on a memory map of `file`, read through a buffer instead
+/// when it cannot be mapped
+fn mapped_batch_main(
+    path: &str,
+    file: File,
+    operation: &str,
+    options: &CliOptions,
+    mut out: Output,
+) {
+    let map = match mapped::Mapped::new(&file) {
+        Ok(map) => map,
+        Err(e) => {
+            if options.decode.verbosity > 0 {
+                eprintln!("batch: cannot map {}: {}; reading it instead", path, e);
+            }
+            return batch_main(BufReader::new(file), operation, options, out);
+        }
+    };
+    let end = options.delimiter() as u8;
+    let records = map.bytes().split(|&b| b == end).map(|record| Ok(Cow::Borrowed(record)));
+    let result = run_batch_records(records, &mut out, io::stderr().lock(), operation, options);
+    conclude_batch(result, operation, options, out);
+}
+
+/// Report the totals of a batch run on stderr and exit accordingly
+fn conclude_batch(
+    result: io::Result<BatchSummary>,
+    operation: &str,
+    options: &CliOptions,
+    out: Output,
+) {
+    match result {
+        Ok(summary) => {
+            conclude(Ok(()), out);
+            if !options.quiet {
+                eprintln!("Processed: {}, failed: {}", summary.processed, summary.failed);
+            }
+            if operation == "analyze" && !options.quiet {
+                let counts: Vec<String> = ReferenceKind::ALL
+                    .iter()
+                    .map(|kind| format!("{}: {}", kind.name(), summary.references[*kind as usize]))
+                    .collect();
+                eprintln!("References: {}", counts.join(", "));
+            }
+            if summary.failed > 0 {
+                process::exit(PARTIAL_EXIT_CODE);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            conclude(Err(ExitCode(USAGE_EXIT_CODE)), out);
+        }
+    }
+}
+
+/// `to-file-url`: `path_to_file_url` for a path given on the command line,
+/// relative paths being taken from the current directory
+fn file_url_for_input(input: &str) -> String {
+    let path = Path::new(input);
+    match std::path::absolute(path) {
+        Ok(absolute) => path_to_file_url(&absolute),
+        Err(_) => path_to_file_url(path),
+    }
+}
+
+/// `query-build`: a query string from `--from-json` and then `key=value`
+/// arguments, each a bare `key` for a parameter without a value
+fn query_build(args: &[String], flags: &[String]) -> Result<String, String> {
+    let mut builder = QueryBuilder::new();
+
+    if let Some(json) = flag_value(flags, "--from-json") {
+        let pairs = parse_flat_json_object(json).map_err(|e| format!("--from-json: {}", e))?;
+        for (key, value) in &pairs {
+            builder.push_pair(key, value.as_deref());
+        }
+    }
+    for arg in args {
+        let (key, value) = QueryEdit::key_value(arg);
+        builder.push_pair(&key, value.as_deref());
+    }
+
+    Ok(builder.finish())
+}
+
+/// `expand`: `expand_template` of the first argument, with variables from
+/// `--vars-json` and then `name=value` arguments, where a value with commas
+/// is a list
+fn expand(args: &[String], flags: &[String]) -> Result<String, String> {
+    let Some((template, assignments)) = args.split_first() else {
+        return Err("expand takes a template".to_string());
+    };
+    let mut vars = match flag_value(flags, "--vars-json") {
+        Some(json) => parse_template_vars_json(json).map_err(|e| format!("--vars-json: {}", e))?,
+        None => Vec::new(),
+    };
+    for arg in assignments {
+        let Some((name, value)) = arg.split_once('=') else {
+            return Err(format!("Expected name=value, got: {}", arg));
+        };
+        let value = if value.contains(',') {
+            TemplateValue::List(value.split(',').map(str::to_string).collect())
+        } else {
+            TemplateValue::String(value.to_string())
+        };
+        vars.push((name.to_string(), value));
+    }
+
+    let strict = flags.iter().any(|f| f == "--strict");
+    expand_template(template, &vars, strict).map_err(|e| e.to_string())
+}
+
+/// `equiv`: compare the two URLs in `args` with `URLEncoder::equivalent_to`
+fn equiv(args: &[String], flags: &[String]) -> Result<Option<Mismatch>, String> {
+    let [left, right] = args else {
+        return Err("equiv takes exactly two URLs".to_string());
+    };
+    let options = EquivOptions {
+        ignore_fragment: flags.iter().any(|f| f == "--ignore-fragment"),
+        ignore_param_order: flags.iter().any(|f| f == "--ignore-param-order"),
+    };
+
+    URLEncoder::new(left.clone())
+        .equivalent_to(&URLEncoder::new(right.clone()), options)
+        .map_err(|e| e.to_string())
+}
+
+/// `diff`: compare the two URLs in `args` with `URLComponents::diff`,
+/// after normalizing both with `--normalize`
+fn diff(args: &[String], flags: &[String]) -> Result<Diff, String> {
+    let [old, new] = args else {
+        return Err("diff takes exactly two URLs".to_string());
+    };
+    let parse = |input: &String| -> Result<URLComponents, String> {
+        let mut url = URLEncoder::new(input.clone()).parse().map_err(|e| e.to_string())?;
+        if flags.iter().any(|f| f == "--normalize") {
+            url.normalize(&NormalizeOptions::default());
+        }
+        Ok(url)
+    };
+
+    Ok(parse(old)?.diff(&parse(new)?))
+}
+
+/// `similar`: the second URL in `args`, or with `--batch` each line of a
+/// file, scored against the first by `similarity`, best match first
+///
+/// A line that does not parse scores 0. Equal scores keep the file order.
+fn similar(args: &[String], flags: &[String]) -> Result<Vec<(f64, String)>, String> {
+    let candidates: Vec<String> = match (args, flag_value(flags, "--batch")) {
+        ([_, other], None) => vec![other.clone()],
+        ([_], Some(path)) => std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path, e))?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        _ => return Err("similar takes two URLs, or a URL and --batch <path>".to_string()),
+    };
+    let parse = |input: &str| -> Result<URLComponents, ParseError> {
+        let mut url = URLEncoder::new(input.to_string()).parse()?;
+        if flags.iter().any(|f| f == "--normalize") {
+            url.normalize(&NormalizeOptions::default());
+        }
+        Ok(url)
+    };
+    let reference = parse(&args[0]).map_err(|e| format!("{}: {}", args[0], e))?;
+
+    let mut scored: Vec<(f64, String)> = candidates
+        .into_iter()
+        .map(|candidate| {
+            let score = parse(&candidate)
+                .map_or(0.0, |url| similarity(&reference, &url, Weights::default()));
+            (score, candidate)
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    Ok(scored)
+}
+
+/// `join`: the base URL in `args[0]` with each later argument appended as
+/// a path segment
+///
+/// Refuses a base with a query or fragment unless `--before-query` says
+/// the segments belong in front of them.
+fn join(args: &[String], flags: &[String]) -> Result<String, String> {
+    let (base, segments) = args.split_first().ok_or("join takes a base URL and segments")?;
+    let mut url = URLEncoder::new(base.clone()).parse().map_err(|e| e.to_string())?;
+
+    if (url.query.is_some() || url.fragment.is_some())
+        && !flags.iter().any(|f| f == "--before-query")
+    {
+        return Err(format!("{} has a query or fragment; pass --before-query to join", base));
+    }
+    for segment in segments {
+        url.join_segment(segment);
+    }
+    Ok(url.to_string())
+}
+
+/// `resolve`: the reference in `args[1]` resolved against the base URL in
+/// `args[0]`
+fn resolve(args: &[String]) -> Result<String, String> {
+    let [base, reference] = args else {
+        return Err("resolve takes a base URL and a reference".to_string());
+    };
+    let base = URLEncoder::new(base.clone()).parse().map_err(|e| e.to_string())?;
+
+    base.resolve(reference).map_err(|e| e.to_string())
+}
+
+/// Untimed passes `bench` makes before the timed ones
+const BENCH_WARMUP_ITERATIONS: usize = 1;
+
+/// BenchReport is what `bench` measured: the corpus and how long each timed
+/// pass over it took
+#[derive(Debug)]
+struct BenchReport {
+    operation: String,
+    urls: usize,
+    bytes: usize,
+    /// One per timed iteration, shortest first
+    times: Vec<Duration>,
+}
+
+impl BenchReport {
+    /// The shortest, median and longest iteration
+    fn min_median_max(&self) -> [Duration; 3] {
+        let times = &self.times;
+        let mid = times.len() / 2;
+        let median = if times.len().is_multiple_of(2) {
+            (times[mid - 1] + times[mid]) / 2
+        } else {
+            times[mid]
+        };
+        [times[0], median, times[times.len() - 1]]
+    }
+
+    /// Seconds, MB/s and URLs/s, each as min, median and max
+    ///
+    /// The fastest iteration gives the max throughput, so the throughput
+    /// rows run opposite to the seconds row.
+    fn stats(&self) -> [[f64; 3]; 3] {
+        let seconds = self.min_median_max().map(|time| time.as_secs_f64());
+        let per_second =
+            |amount: f64| [amount / seconds[2], amount / seconds[1], amount / seconds[0]];
+        [seconds, per_second(self.bytes as f64 / (1024.0 * 1024.0)), per_second(self.urls as f64)]
+    }
+
+    fn text(&self) -> String {
+        let [seconds, megabytes, urls] = self.stats();
+        let row = |name: &str, [min, median, max]: [f64; 3], precision: usize| {
+            format!("{:<8}{:>14.p$}{:>14.p$}{:>14.p$}\n", name, min, median, max, p = precision)
+        };
+        format!(
+            "bench {}: {} URLs ({} bytes), {} iterations after {} warm-up\n\
+             {:<8}{:>14}{:>14}{:>14}\n{}{}{}",
+            self.operation,
+            self.urls,
+            self.bytes,
+            self.times.len(),
+            BENCH_WARMUP_ITERATIONS,
+            "",
+            "min",
+            "median",
+            "max",
+            row("ms", seconds.map(|s| s * 1000.0), 3),
+            row("MB/s", megabytes, 2),
+            row("URLs/s", urls, 0)
+        )
+    }
+
+    fn json(&self) -> String {
+        let [seconds, megabytes, urls] = self.stats();
+        let object = |[min, median, max]: [f64; 3]| {
+            format!("{{\"min\":{},\"median\":{},\"max\":{}}}", min, median, max)
+        };
+        format!(
+            "{{\"operation\":\"{}\",\"urls\":{},\"bytes\":{},\"iterations\":{},\
+             \"warmup_iterations\":{},\"seconds\":{},\"mb_per_second\":{},\
+             \"urls_per_second\":{}}}",
+            self.operation,
+            self.urls,
+            self.bytes,
+            self.times.len(),
+            BENCH_WARMUP_ITERATIONS,
+            object(seconds),
+            object(megabytes),
+            object(urls)
+        )
+    }
+}
+
+/// `bench`: run `--op` over every line of the `--file` corpus, once to
+/// warm up and then `--iterations` times against a monotonic clock
+///
+/// The corpus is read into memory first, so file I/O is never timed, and
+/// results are dropped rather than printed. Blank lines are skipped.
+fn bench(args: &[String], flags: &[String]) -> Result<BenchReport, String> {
+    if let Some(arg) = args.first() {
+        return Err(format!("Unexpected argument: {}; pass the corpus with --file", arg));
+    }
+    let path = flag_value(flags, "--file").ok_or("bench needs a corpus: --file <path>")?;
+    let operation = flag_value(flags, "--op").unwrap_or("encode");
+    if !OPERATIONS.contains(&operation) {
+        return Err(unknown_operation(operation));
+    }
+    let iterations = match flag_value(flags, "--iterations").map(str::parse::<usize>) {
+        Some(Ok(iterations)) if iterations > 0 => iterations,
+        Some(_) => return Err("Invalid --iterations: expected a positive number".to_string()),
+        None => 10,
+    };
+    let corpus =
+        std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let lines: Vec<&str> = corpus.lines().filter(|line| !line.is_empty()).collect();
+    if lines.is_empty() {
+        return Err(format!("{} has no URLs", path));
+    }
+    let options = CliOptions::try_parse(&[])?;
+
+    let pass = || {
+        for line in &lines {
+            std::hint::black_box(batch_line(line, operation, &options)).ok();
+        }
+    };
+    for _ in 0..BENCH_WARMUP_ITERATIONS {
+        pass();
+    }
+    let mut times: Vec<Duration> = (0..iterations)
+        .map(|_| {
+            let start = Instant::now();
+            pass();
+            // A clock tick too coarse for a tiny corpus still counts as time
+            start.elapsed().max(Duration::from_nanos(1))
+        })
+        .collect();
+    times.sort();
+
+    Ok(BenchReport {
+        operation: operation.to_string(),
+        urls: lines.len(),
+        bytes: lines.iter().map(|line| line.len()).sum(),
+        times,
+    })
+}
+
+/// `formbody`: a form-encoded body from `key=value` arguments, each a bare
+/// `key` for a parameter without a value, or with `--json` from flat JSON
+/// objects
+///
+/// In a key, `\=` is a literal `=` and `\\` a literal backslash; the value
+/// is everything after the first unescaped `=`.
+fn formbody(args: &[String], flags: &[String]) -> Result<String, String> {
+    let mut builder = QueryBuilder::new();
+
+    for arg in args {
+        if flags.iter().any(|f| f == "--json") {
+            let pairs = parse_flat_json_object(arg).map_err(|e| format!("--json: {}", e))?;
+            for (key, value) in &pairs {
+                builder.push_pair(key, value.as_deref());
+            }
+        } else {
+            let (key, value) = escaped_key_value(arg);
+            builder.push_pair(&key, value);
+        }
+    }
+
+    Ok(builder.finish())
+}
+
+/// Split `key=value` at the first `=` not escaped as `\=`, unescaping the
+/// key
+fn escaped_key_value(arg: &str) -> (String, Option<&str>) {
+    let mut key = String::new();
+    let mut chars = arg.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.clone().next() {
+                Some((_, next @ ('=' | '\\'))) => {
+                    key.push(next);
+                    chars.next();
+                }
+                _ => key.push('\\'),
+            },
+            '=' => return (key, Some(&arg[i + 1..])),
+            c => key.push(c),
+        }
+    }
+
+    (key, None)
+}
+
+/// `form`: a request body from curl `--data-urlencode` style arguments;
+/// see `FormEncoder::push_arg`
+fn form(args: &[String]) -> Result<String, String> {
+    let mut encoder = FormEncoder::new();
+    for arg in args {
+        encoder.push_arg(arg).map_err(|e| e.to_string())?;
+    }
+    Ok(encoder.finish())
+}
+
+/// What `:help` prints in the repl
+const REPL_HELP: &str = "\
+:mode <operation>    Apply this operation to each line, as in :mode decode
+:set <flag> on|off   Turn a switch on or off, as in :set lenient on
+:set <flag> <value>  Set a value flag, as in :set format json
+:set                 List the flags that are set
+:reset               Clear every flag
+:history             List the lines entered so far
+!<n>, !!             Enter history line n again, or the last line
+:help                This list
+:quit                Leave the session; so does Ctrl-D";
+
+/// Repl is one `repl` session: a mode, the flags set, and the lines
+/// entered
+///
+/// Lines are read as the terminal hands them over, so editing is what
+/// the terminal's line discipline offers (arrow keys need a wrapper such
+/// as `rlwrap`); history lives in the session and is recalled with `!<n>`.
+struct Repl {
+    /// The operation each input line goes through
+    mode: String,
+    /// Set on the command line or with `:set`, as they would appear on
+    /// the command line
+    flags: Vec<String>,
+    /// The `GLOBAL_FLAGS` from the command line, kept through `:reset`
+    globals: Vec<String>,
+    options: CliOptions,
+    history: Vec<String>,
+}
+
+impl Repl {
+    /// A session in encode mode starting with the command line's `flags`
+    fn new(flags: &[String]) -> Result<Repl, String> {
+        let mut globals = Vec::new();
+        let mut set = Vec::new();
+        let mut flags = flags.iter();
+        while let Some(flag) = flags.next() {
+            if INPUT_OUTPUT_FLAGS.contains(&flag.as_str()) {
+                return Err(format!("{} is not available in the repl", flag));
+            }
+            let to = if GLOBAL_FLAGS.contains(&flag.as_str()) { &mut globals } else { &mut set };
+            to.push(flag.clone());
+            if VALUE_FLAGS.contains(&flag.as_str()) {
+                to.extend(flags.next().cloned());
+            }
+        }
+
+        let mut repl = Repl {
+            mode: "encode".to_string(),
+            flags: Vec::new(),
+            globals,
+            options: CliOptions::parse(&[]),
+            history: Vec::new(),
+        };
+        repl.update(set)?;
+        Ok(repl)
+    }
+
+    /// Read lines from `input` until end of input or `:quit`, writing
+    /// results to `out` and errors to stderr
+    ///
+    /// With `prompt`, each line is asked for with the mode as prompt.
+    fn run<R: BufRead>(&mut self, input: R, out: &mut dyn Write, prompt: bool) -> io::Result<()> {
+        let mut lines = input.lines();
+
+        loop {
+            if prompt {
+                write!(out, "{}> ", self.mode)?;
+                out.flush()?;
+            }
+            let Some(line) = lines.next() else {
+                if prompt {
+                    writeln!(out)?;
+                }
+                return Ok(());
+            };
+            let line = line?;
+            let line = line.strip_suffix('\r').unwrap_or(&line);
+            let line = match self.recall(line) {
+                Ok(Some(recalled)) => {
+                    writeln!(out, "{}", recalled)?;
+                    recalled
+                }
+                Ok(None) => line.to_string(),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    continue;
+                }
+            };
+            if line.is_empty() {
+                continue;
+            }
+            self.history.push(line.clone());
+
+            if line == ":quit" || line == ":q" {
+                return Ok(());
+            }
+            match line.strip_prefix(':') {
+                Some(command) => {
+                    if let Err(e) = self.command(command, out) {
+                        eprintln!("Error: {}", e);
+                    }
+                }
+                // Errors are reported as run_operation reports them
+                None => {
+                    let _ = run_operation(&line, &self.mode, &self.options, out);
+                }
+            }
+            out.flush()?;
+        }
+    }
+
+    /// The history line a `!<n>` or `!!` line stands for
+    fn recall(&self, line: &str) -> Result<Option<String>, String> {
+        let Some(reference) = line.strip_prefix('!') else {
+            return Ok(None);
+        };
+        let entry = if reference == "!" {
+            self.history.last()
+        } else {
+            match reference.parse::<usize>() {
+                Ok(n) if n > 0 => self.history.get(n - 1),
+                _ => return Ok(None),
+            }
+        };
+        match entry {
+            Some(entry) => Ok(Some(entry.clone())),
+            None => Err(format!("no history line {}", line)),
+        }
+    }
+
+    /// Run a `:` command, without its colon
+    fn command(&mut self, command: &str, out: &mut dyn Write) -> Result<(), String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let written = |result: io::Result<()>| result.map_err(|e| e.to_string());
+
+        match words[..] {
+            ["mode"] => written(writeln!(out, "{}", self.mode)),
+            ["mode", operation] => {
+                if !OPERATIONS.contains(&operation) {
+                    return Err(unknown_operation(operation));
+                }
+                self.mode = operation.to_string();
+                Ok(())
+            }
+            ["set"] => written(writeln!(out, "{}", self.flags.join(" "))),
+            ["set", name, value] => self.set(name, value),
+            ["set", name] => Err(format!("{} needs a value; use on or off for a switch", name)),
+            ["reset"] => self.update(Vec::new()),
+            ["history"] => {
+                for (i, line) in self.history.iter().enumerate() {
+                    written(writeln!(out, "{:4}  {}", i + 1, line))?;
+                }
+                Ok(())
+            }
+            ["help"] => written(writeln!(out, "{}", REPL_HELP)),
+            _ => Err(format!("Unknown command: :{}. Type :help for the commands", command)),
+        }
+    }
+
+    /// `:set`: turn switch `name` on or off, or give value flag `name`
+    /// a value, replacing its earlier one
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let flag = if name.starts_with("--") { name.to_string() } else { format!("--{}", name) };
+        if INPUT_OUTPUT_FLAGS.contains(&flag.as_str()) {
+            return Err(format!("{} is not available in the repl", flag));
+        }
+        check_flags(std::slice::from_ref(&flag), OPERATION_FLAGS)?;
+        let takes_value = VALUE_FLAGS.contains(&flag.as_str());
+
+        let mut flags = Vec::with_capacity(self.flags.len() + 2);
+        let mut old = self.flags.iter();
+        while let Some(f) = old.next() {
+            let value = if VALUE_FLAGS.contains(&f.as_str()) { old.next() } else { None };
+            if *f != flag {
+                flags.push(f.clone());
+                flags.extend(value.cloned());
+            }
+        }
+        match value {
+            "off" => {}
+            "on" if !takes_value => flags.push(flag),
+            _ if takes_value => flags.extend([flag, value.to_string()]),
+            _ => return Err(format!("{} is a switch; use on or off", flag)),
+        }
+        self.update(flags)
+    }
+
+    /// Switch to `flags`, keeping the current ones if they do not parse
+    fn update(&mut self, flags: Vec<String>) -> Result<(), String> {
+        let all: Vec<String> = self.globals.iter().chain(&flags).cloned().collect();
+        self.options = CliOptions::try_parse(&all)?;
+        self.flags = flags;
+        Ok(())
+    }
+}
+
+/// Stream `reader` through `operation` if it supports streaming; returns
+/// `None` when the input has to be read whole instead
+fn stream_operation<R: Read>(
+    reader: &mut R,
+    operation: &str,
+    options: &CliOptions,
+    out: &mut dyn Write,
+) -> Option<Result<(), StreamError>> {
+    let stream_options = options.stream_options();
+
+    // Codecs take the whole input at once; only encoding reads it as bytes
+    match options.codec_for(operation) {
+        Some((codec, false)) => {
+            let mut bytes = Vec::new();
+            let result = reader
+                .read_to_end(&mut bytes)
+                .and_then(|_| writeln!(out, "{}", codec.encode(&bytes)));
+            return Some(result.map_err(StreamError::Io));
+        }
+        Some((_, true)) => return None,
+        None => {}
+    }
+    match operation {
+        "encode" if options.component.is_none() && !options.idempotent && !options.url => Some(
+            encode_stream(reader, out, &stream_options).map_err(StreamError::Io),
+        ),
+        "decode" if !options.recursive && !options.hexdump => {
+            Some(decode_stream(reader, out, &stream_options))
+        }
+        _ => None,
+    }
+}
+
+/// Report a command-line mistake on stderr and exit with
+/// `USAGE_EXIT_CODE`
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(USAGE_EXIT_CODE);
+}
+
+/// Run the subcommand `name` on `args`, writing its result to `out`
+///
+/// Exit codes for equiv and diff follow cmp(1): 0 same, 1 different, 2
+/// trouble. The others exit with `USAGE_EXIT_CODE` on error.
+fn run_subcommand(
+    name: &str,
+    args: &[String],
+    flags: &[String],
+    out: &mut dyn Write,
+) -> Result<(), ExitCode> {
+    let trouble = if name == "equiv" || name == "diff" { 2 } else { USAGE_EXIT_CODE };
+    let fail = |e: String| {
+        eprintln!("Error: {}", e);
+        ExitCode(trouble)
+    };
+
+    match name {
+        "query-build" => writeln!(out, "{}", query_build(args, flags).map_err(fail)?)?,
+        "formbody" => writeln!(out, "{}", formbody(args, flags).map_err(fail)?)?,
+        "form" => writeln!(out, "{}", form(args).map_err(fail)?)?,
+        "join" => writeln!(out, "{}", join(args, flags).map_err(fail)?)?,
+        "expand" => writeln!(out, "{}", expand(args, flags).map_err(fail)?)?,
+        "completions" => write!(out, "{}", completions(args).map_err(fail)?)?,
+        "bench" => {
+            let report = bench(args, flags).map_err(fail)?;
+            if flags.iter().any(|f| f == "--json") {
+                writeln!(out, "{}", report.json())?;
+            } else {
+                write!(out, "{}", report.text())?;
+            }
+        }
+        "repl" => {
+            if let Some(arg) = args.first() {
+                return Err(fail(format!("Unexpected argument: {}", arg)));
+            }
+            let stdin = io::stdin();
+            Repl::new(flags).map_err(fail)?.run(stdin.lock(), out, stdin.is_terminal())?;
+        }
+        "resolve" => writeln!(out, "{}", resolve(args).map_err(fail)?)?,
+        "similar" => {
+            let batch = flag_value(flags, "--batch").is_some();
+            for (score, url) in similar(args, flags).map_err(fail)? {
+                if batch {
+                    writeln!(out, "{:.3}\t{}", score, url)?;
+                } else {
+                    writeln!(out, "{:.3}", score)?;
+                }
+            }
+        }
+        "equiv" => match equiv(args, flags).map_err(fail)? {
+            None => writeln!(out, "Equivalent")?,
+            Some(mismatch) => {
+                writeln!(out, "Not equivalent: {}", mismatch)?;
+                return Err(ExitCode(1));
+            }
+        },
+        "diff" => {
+            let changes = diff(args, flags).map_err(fail)?;
+            if changes.is_empty() {
+                writeln!(out, "identical")?;
+                return Ok(());
+            }
+            let styler = Styler::stdout(color_choice(flags).map_err(fail)?);
+            for line in changes.lines() {
+                writeln!(out, "{}", styler.diff_line(&line))?;
+            }
+            return Err(ExitCode(1));
+        }
+        _ => {
+            eprintln!("Unknown command: {}", name);
+            return Err(ExitCode(USAGE_EXIT_CODE));
+        }
+    }
+
+    Ok(())
+}
+
+/// Drop a single trailing newline (`\n` or `\r\n`), as shells add one to
+/// piped text
+fn trim_trailing_newline(text: &str) -> &str {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    text.strip_suffix('\r').unwrap_or(text)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (positionals, flags) = match split_args(&args) {
+        Ok(split) => split,
+        Err(e) => usage_error(&e),
+    };
+    let flags = &flags[..];
+    let file = flag_value(flags, "--file");
+    let batch = flag_value(flags, "--batch");
+    let stdin_piped = !io::stdin().is_terminal();
+
+    if flags.iter().any(|f| f == "--help") {
+        match positionals.iter().find_map(|p| CommandHelp::find(p)) {
+            Some(command) => print!("{}", command.render()),
+            None => print!("{}", overview_help()),
+        }
+        return;
+    }
+    if flags.iter().any(|f| f == "--version") {
+        println!("{}", version_text());
+        return;
+    }
+
+    let subcommand = positionals
+        .first()
+        .and_then(|first| SUBCOMMANDS.iter().find(|(name, _)| name == first));
+    // The repl starts with whatever operation flags it is given
+    let accepted = match subcommand {
+        Some((name, flags)) if *name != "repl" => flags,
+        _ => OPERATION_FLAGS,
+    };
+    if let Err(e) = check_flags(flags, accepted) {
+        usage_error(&e);
+    }
+    if let Some((name, _)) = subcommand {
+        let mut out = if flags.iter().any(|f| f == "--quiet") {
+            Output::sink()
+        } else {
+            Output::stdout()
+        };
+        let result = run_subcommand(name, &positionals[1..], flags, &mut out);
+        return conclude(result, out);
+    }
+
+    let from_file = file.or(batch).is_some();
+    if positionals.is_empty() && !stdin_piped && !from_file && io::stdout().is_terminal() {
+        let mut out = Output::stdout();
+        let result = run_subcommand("repl", &[], flags, &mut out);
+        return conclude(result, out);
+    }
+    if positionals.is_empty() && !stdin_piped && !from_file {
+        eprintln!("{}\n\nRun 'url_encoder --help' for the operations and flags.", USAGE);
+        process::exit(USAGE_EXIT_CODE);
+    }
+    let (input, operation) = match input_and_operation(&positionals, from_file, stdin_piped) {
+        Ok(named) => named,
+        Err(e) => usage_error(&e),
+    };
+    let operation = operation.unwrap_or("encode");
+    if !OPERATIONS.contains(&operation) {
+        usage_error(&unknown_operation(operation));
+    }
+    if let Err(e) = check_operation_flags(flags, operation) {
+        usage_error(&e);
+    }
+    if operation == "anonymize" && flag_value(flags, "--key").is_none() {
+        usage_error("anonymize needs --key: the secret its tokens are keyed with");
+    }
+    let options = CliOptions::parse(flags);
+    let raw = flags.iter().any(|f| f == "--raw");
+
+    if let Some(path) = batch {
+        match File::open(path) {
+            Ok(f) if options.mmap => {
+                mapped_batch_main(path, f, operation, &options, options.open_output())
+            }
+            Ok(f) => batch_main(BufReader::new(f), operation, &options, options.open_output()),
+            Err(e) => {
+                eprintln!("Error: cannot open {}: {}", path, e);
+                process::exit(USAGE_EXIT_CODE);
+            }
+        }
+        return;
+    }
+
+    // Files and `--raw` stdin are one blob, streamed when the operation
+    // allows it. Otherwise stdin is taken line by line.
+    let mut reader: Box<dyn Read> = match (file, input) {
+        (Some(path), _) => match File::open(path) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!("Error: cannot open {}: {}", path, e);
+                process::exit(USAGE_EXIT_CODE);
+            }
+        },
+        (None, Some("-")) => Box::new(io::stdin()),
+        (None, input) => {
+            let mut out = options.open_output();
+            let result = run_operation(input.unwrap_or_default(), operation, &options, &mut out);
+            return conclude(result, out);
+        }
+    };
+
+    // NUL records may hold newlines, so they never go through lines.
+    if options.null {
+        return batch_main(BufReader::new(reader), operation, &options, options.open_output());
+    }
+
+    let blob = file.is_some() || raw;
+    if blob {
+        let mut out = options.open_output();
+        match stream_operation(&mut reader, operation, &options, &mut out) {
+            Some(Ok(())) => return conclude(Ok(()), out),
+            Some(Err(e)) => {
+                eprintln!("Error: {}", e);
+                let code = match e {
+                    StreamError::Io(_) => USAGE_EXIT_CODE,
+                    StreamError::Decode(_) => PARSE_EXIT_CODE,
+                };
+                return conclude(Err(ExitCode(code)), out);
+            }
+            None => {}
+        }
+    }
+
+    let mut text = String::new();
+    if let Err(e) = reader.read_to_string(&mut text) {
+        eprintln!("Error: cannot read input: {}", e);
+        process::exit(USAGE_EXIT_CODE);
+    }
+    let text = if blob {
+        &text[..]
+    } else {
+        trim_trailing_newline(&text)
+    };
+
+    // A single line behaves exactly like a command-line argument; more than
+    // one is batch mode. `extract` takes any text, so offsets span lines.
+    let mut out = options.open_output();
+    if !blob && operation != "extract" && text.contains('\n') {
+        batch_main(text.as_bytes(), operation, &options, out);
+    } else {
+        let result = run_operation(text, operation, &options, &mut out);
+        conclude(result, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jai::codec;
+    use std::collections::HashSet;
+
+    #[test]
+    fn trim_trailing_newline_removes_only_one() {
+        assert_eq!(trim_trailing_newline("a%20b\n"), "a%20b");
+        assert_eq!(trim_trailing_newline("a%20b\r\n"), "a%20b");
+        assert_eq!(trim_trailing_newline("a\n\n"), "a\n");
+        assert_eq!(trim_trailing_newline("a"), "a");
+    }
+
+    fn batch(input: &str, operation: &str, flags: &[&str]) -> (String, String, BatchSummary) {
+        let flags: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+        let options = CliOptions::parse(&flags);
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let summary = run_batch(input.as_bytes(), &mut out, &mut err, operation, &options).unwrap();
+        (
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+            summary,
+        )
+    }
+
+    #[test]
+    fn batch_processes_each_line_and_skips_comments() {
+        let (out, err, summary) = batch("# urls\na b\n\nc/d\r\n#x y\n", "encode", &[]);
+
+        assert_eq!(out, "a%20b\nc%2Fd\n");
+        assert_eq!(err, "");
+        assert_eq!(summary, BatchSummary { processed: 2, failed: 0, ..BatchSummary::default() });
+    }
+
+    #[test]
+    fn batch_reports_failures_with_line_numbers() {
+        let (out, err, summary) = batch("a%20b\nbad%ZZ\nc+d\n%C3", "decode", &[]);
+
+        assert_eq!(out, "a b\nc d\n");
+        assert_eq!(
+            err,
+            "line 2: Error: Invalid hex sequence %ZZ at byte offset 3\n\
+             line 4: Error: Invalid UTF-8 sequence %C3 at byte offset 0\n"
+        );
+        assert_eq!(summary, BatchSummary { processed: 4, failed: 2, ..BatchSummary::default() });
+    }
+
+    #[test]
+    fn batch_analyze_prints_one_line_per_url() {
+        let (out, _, summary) = batch("https://a.com/x?y=1\nplain\n", "analyze", &[]);
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(summary.processed, 2);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("https://a.com/x?y=1\tlength=19 "));
+        assert!(lines[1].starts_with("plain\tlength=5 "));
+    }
+
+    #[test]
+    fn batch_honours_decode_flags() {
+        let (out, _, summary) = batch("100%\n%25253C\n", "decode", &["--lenient", "--recursive"]);
+
+        assert_eq!(out, "100%\n<\n");
+        assert_eq!(summary.failed, 0);
+    }
+
+    #[test]
+    fn parallel_batch_matches_sequential_output() {
+        let mut input = String::new();
+        for i in 0..5000 {
+            match i % 7 {
+                0 => input.push_str("bad%ZZ"),
+                1 => input.push_str("# comment"),
+                2 => {}
+                _ => input.push_str(&format!("line%20{}+with%2Fescapes", i)),
+            }
+            input.push('\n');
+        }
+
+        let sequential = batch(&input, "decode", &[]);
+        for jobs in ["2", "3", "8"] {
+            assert_eq!(batch(&input, "decode", &["--jobs", jobs]), sequential);
+        }
+        assert!(sequential.2.failed > 0);
+    }
+
+    #[test]
+    fn parallel_batch_with_more_jobs_than_lines() {
+        let expected = batch("a b\nc%d\n", "encode", &[]);
+
+        assert_eq!(batch("a b\nc%d\n", "encode", &["--jobs", "64"]), expected);
+        assert_eq!(batch("", "encode", &["--jobs", "64"]).2, BatchSummary::default());
+    }
+
+    #[test]
+    fn batch_csv_emits_one_header_and_a_row_per_url() {
+        let input = "https://a.com/x/y?p=1&q=2,3\nhttp://h/\"quoted\"\nnot a url\n";
+        let (out, _, _) = batch(input, "analyze", &["--csv"]);
+
+        assert_eq!(
+            out,
+            "url,total_length,encoded_length,special_chars,scheme,host,\
+             path_segments,query_params,trailing_slash,extension\n\
+             \"https://a.com/x/y?p=1&q=2,3\",27,47,1,https,a.com,2,2,false,\n\
+             \"http://h/\"\"quoted\"\"\",17,29,2,http,h,1,0,false,\n\
+             not a url,9,13,2,,,0,0,false,\n"
+        );
+    }
+
+    #[test]
+    fn batch_csv_header_is_written_once_or_not_at_all() {
+        let input = "a\n".repeat(BATCH_LINES_PER_JOB * 3);
+        let (out, _, _) = batch(&input, "analyze", &["--csv", "--jobs", "2"]);
+        assert_eq!(out.matches("url,total_length").count(), 1);
+        assert_eq!(out.lines().count(), BATCH_LINES_PER_JOB * 3 + 1);
+
+        let (out, _, _) = batch("", "analyze", &["--csv"]);
+        assert_eq!(out, format!("{}\n", ANALYZE_CSV_HEADER));
+
+        let (out, _, _) = batch("a\n", "analyze", &["--csv", "--no-header"]);
+        assert_eq!(out, "a,1,1,0,,,0,0,false,\n");
+    }
+
+    #[test]
+    fn params_lines_show_keys_without_values_alone() {
+        let params = URLEncoder::new("?a=1&flag&b=".to_string()).parse_query();
+
+        assert_eq!(params_lines(&params), ["a = 1", "flag", "b = "]);
+    }
+
+    #[test]
+    fn query_map_keeps_repeated_values() {
+        let map = URLEncoder::new("?tag=a&x=1&tag=b&flag&tag=".to_string()).query_map();
+
+        assert_eq!(map.get_all("tag"), ["a", "b", ""]);
+        assert_eq!(map.get_first("tag"), Some("a"));
+        assert_eq!(map.get_first("x"), Some("1"));
+        assert!(map.contains_key("flag"));
+        assert_eq!(map.get_all("flag"), [] as [String; 0]);
+        assert_eq!(map.get_first("flag"), None);
+        assert!(!map.contains_key("missing"));
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            [
+                ("tag", Some("a")),
+                ("x", Some("1")),
+                ("tag", Some("b")),
+                ("flag", None),
+                ("tag", Some("")),
+            ]
+        );
+        assert_eq!(grouped_params_lines(&map), ["tag: [a, b, \"\"]", "x: [1]", "flag: []"]);
+
+        let map = URLEncoder::new("https://e.com/?e=&flag".to_string()).query_map();
+        assert_eq!(grouped_params_lines(&map), ["e: [\"\"]", "flag: []"]);
+    }
+
+    fn rewrite(input: &str, edits: &[QueryEdit]) -> String {
+        URLEncoder::new(input.to_string()).rewrite_query(edits).unwrap()
+    }
+
+    fn set(key: &str, value: &str) -> QueryEdit {
+        QueryEdit::Set(key.to_string(), Some(value.to_string()))
+    }
+
+    fn pair(key: &str, value: Option<&str>) -> (String, Option<String>) {
+        (key.to_string(), value.map(str::to_string))
+    }
+
+    #[test]
+    fn query_edits_keep_command_line_order() {
+        let args = ["--set", "lang=en", "--form", "--remove", "apikey", "--append", "t"];
+        let flags: Vec<String> = args.iter().map(|f| f.to_string()).collect();
+
+        assert_eq!(
+            query_edits(&flags, MergePolicy::KeepExisting),
+            [
+                set("lang", "en"),
+                QueryEdit::Remove("apikey".to_string()),
+                QueryEdit::Append("t".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_params_flag_is_an_ordered_edit() {
+        let args = ["--append", "a=2", "--sort-params", "--append", "a=1"];
+        let flags: Vec<String> = args.iter().map(|f| f.to_string()).collect();
+        let edits = query_edits(&flags, MergePolicy::KeepExisting);
+
+        assert_eq!(edits[1], QueryEdit::Sort);
+        assert_eq!(rewrite("https://e.com/?b=1", &edits), "https://e.com/?a=2&b=1&a=1");
+    }
+
+    #[test]
+    fn redaction_never_touches_encode_or_decode_results() {
+        let (out, _, _) = batch("a%20b?token=x\n", "decode", &["--redact"]);
+        assert_eq!(out, "a b?token=x\n");
+
+        let (out, _, _) = batch("https://e.com/?token=x\n", "analyze", &["--redact-param", "q"]);
+        assert!(out.starts_with("https://e.com/?token=****\t"));
+    }
+
+    #[test]
+    fn query_build_round_trips_through_parse_query() {
+        let args: Vec<String> = ["name=John Doe", "q=a&b", "k=v=w", "flag", "é=ü"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let query = query_build(&args, &[]).unwrap();
+
+        assert_eq!(query, "name=John+Doe&q=a%26b&k=v%3Dw&flag&%C3%A9=%C3%BC");
+        assert_eq!(
+            URLEncoder::new(query).parse_query(),
+            [
+                pair("name", Some("John Doe")),
+                pair("q", Some("a&b")),
+                pair("k", Some("v=w")),
+                pair("flag", None),
+                pair("é", Some("ü")),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_build_from_flat_json() {
+        let flags = vec![
+            "--from-json".to_string(),
+            r#"{"a": "b c", "n": 1.5, "t": true, "z": null, "u": "é😀\"/"}"#.to_string(),
+        ];
+
+        assert_eq!(
+            query_build(&["x=1".to_string()], &flags).unwrap(),
+            "a=b+c&n=1.5&t=true&z&u=%C3%A9%F0%9F%98%80%22%2F&x=1"
+        );
+        assert_eq!(parse_flat_json_object(" { } "), Ok(vec![]));
+        for bad in [
+            r#"{"a": {"b": 1}}"#,
+            r#"{"a": [1]}"#,
+            r#"["a"]"#,
+            r#"{"a": nope}"#,
+            r#"{"a": 1"#,
+            r#"{"a": "\ud800"}"#,
+            r#"{"a": 1} x"#,
+        ] {
+            assert!(parse_flat_json_object(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn merge_query_flag_applies_conflict_policy() {
+        let args = ["--merge-query", "?utm_source=newsletter&lang=en"];
+        let flags: Vec<String> = args.iter().map(|f| f.to_string()).collect();
+        let edits = query_edits(&flags, MergePolicy::Overwrite);
+
+        assert_eq!(
+            rewrite("https://e.com/?lang=de&redirect=https://x", &edits),
+            "https://e.com/?lang=en&redirect=https%3A%2F%2Fx&utm_source=newsletter"
+        );
+    }
+
+    #[test]
+    fn equiv_options_ignore_param_order_and_fragments() {
+        let options = EquivOptions { ignore_param_order: true, ignore_fragment: true };
+        let args = |left: &str, right: &str| [left.to_string(), right.to_string()];
+
+        let equivalent = |left: &str, right: &str| {
+            URLEncoder::new(left.to_string())
+                .equivalent_to(&URLEncoder::new(right.to_string()), options)
+                .unwrap()
+        };
+
+        assert_eq!(equivalent("http://a.com/?a=1&b=2#x", "http://a.com/?b=2&a=1#y"), None);
+        assert!(equivalent("http://a.com/?a=1", "http://a.com/?a=2").is_some());
+        let mismatch = equiv(&args("http://a.com/#x", "http://a.com/"), &[]).unwrap();
+        assert_eq!(mismatch.unwrap().component, "fragment");
+        let flags = ["--ignore-fragment".to_string()];
+        assert_eq!(equiv(&args("http://a.com/#x", "http://a.com/"), &flags), Ok(None));
+        assert!(equiv(&args("http://a.com/", "no scheme"), &[]).is_err());
+        assert!(equiv(&["http://a.com/".to_string()], &[]).is_err());
+    }
+
+    fn diff_lines(old: &str, new: &str, flags: &[&str]) -> Vec<String> {
+        let args = [old.to_string(), new.to_string()];
+        let flags: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+        diff(&args, &flags).unwrap().lines()
+    }
+
+    #[test]
+    fn diff_removed_fragment_and_params() {
+        assert_eq!(
+            diff_lines("https://e.com/p?x=1&flag#top", "https://e.com/q", &[]),
+            ["- path: /p", "+ path: /q", "- query: x = 1", "- query: flag", "- fragment: top"]
+        );
+    }
+
+    #[test]
+    fn diff_host_case_only_counts_without_normalize() {
+        let (old, new) = ("https://Example.COM:443/a/./b", "https://example.com/a/b");
+
+        assert_eq!(
+            diff_lines(old, new, &[]),
+            [
+                "- host: Example.COM",
+                "+ host: example.com",
+                "- port: 443",
+                "- path: /a/./b",
+                "+ path: /a/b",
+            ]
+        );
+        assert_eq!(diff_lines(old, new, &["--normalize"]), [] as [String; 0]);
+        let args = [old.to_string(), new.to_string()];
+        assert!(diff(&args, &["--normalize".to_string()]).unwrap().is_empty());
+        assert!(diff(&args[..1], &[]).is_err());
+    }
+
+    #[test]
+    fn resolve_against_hostname_only_base_and_cli() {
+        let base = URLEncoder::new("https://example.com".to_string()).parse().unwrap();
+        assert_eq!(base.resolve("a/./b").unwrap(), "https://example.com/a/b");
+        assert_eq!(base.resolve("//u@h:8080/p/../q").unwrap(), "https://u@h:8080/q");
+        assert_eq!(
+            base.resolve("//h:99999/"),
+            Err(ParseError::InvalidPort { found: "99999".to_string() })
+        );
+
+        let args = ["https://example.com/a/b/c".to_string(), "../d?x=1".to_string()];
+        assert_eq!(resolve(&args), Ok("https://example.com/a/d?x=1".to_string()));
+        assert!(resolve(&args[..1]).is_err());
+    }
+
+    #[test]
+    fn join_refuses_query_or_fragment_unless_before_query() {
+        let args = |items: &[&str]| -> Vec<String> {
+            items.iter().map(|item| item.to_string()).collect()
+        };
+
+        assert_eq!(
+            join(&args(&["https://e.com/v1/", "a b", "c"]), &[]).unwrap(),
+            "https://e.com/v1/a%20b/c"
+        );
+        assert!(join(&args(&["https://e.com/v1?x=1", "a"]), &[]).is_err());
+        assert!(join(&args(&["https://e.com/v1#top", "a"]), &[]).is_err());
+        assert_eq!(
+            join(&args(&["https://e.com/v1?x=1#top", "a"]), &args(&["--before-query"])).unwrap(),
+            "https://e.com/v1/a?x=1#top"
+        );
+        assert!(join(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn batch_analyze_counts_reference_kinds() {
+        // A `#fragment` line would be a comment, so none are counted here
+        let input = "https://a.com/\n//cdn.com/x\n/p\nq\n?x=1\n/r\nnot a url\n";
+        let (_, _, summary) = batch(input, "analyze", &[]);
+
+        assert_eq!(summary.references, [1, 1, 2, 2, 0]);
+        assert_eq!(batch(input, "analyze", &["--jobs", "3"]).2.references, [1, 1, 2, 2, 0]);
+        assert_eq!(batch(input, "encode", &[]).2.references, [0; 5]);
+    }
+
+    #[test]
+    fn batch_validate_marks_valid_lines() {
+        let (out, err, summary) =
+            batch("https://a.com/\nfoo+bar://a/\nnope\nhttp://a:x/\n", "validate", &[]);
+
+        assert_eq!(out, "valid\nvalid\tscheme foo+bar is not a common registered scheme\n");
+        assert_eq!(summary.failed, 2);
+        assert!(err.starts_with("line 3: Error: Missing scheme"));
+    }
+
+    #[test]
+    fn data_url_components_and_batch() {
+        let encoder = URLEncoder::new("data:text/plain;base64,SGVsbG8=".to_string());
+        assert_eq!(
+            encoder.extract_components(),
+            [
+                "Reference: absolute URL",
+                "Protocol: data",
+                "Media Type: text/plain",
+                "Encoding: base64",
+                "Payload: 5 bytes",
+            ]
+        );
+        let encoder = URLEncoder::new("data:;base64,%3D%3D%3D".to_string());
+        assert_eq!(
+            encoder.extract_components(),
+            ["URL: Invalid base64 padding === at byte offset 0"]
+        );
+
+        let (out, err, _) = batch("data:,a%20b\ndata:;base64,/w==\ndata:x\n", "data-decode", &[]);
+        assert_eq!(out, "a b\n");
+        assert!(err.contains("payload is not UTF-8"), "{}", err);
+        assert!(err.contains("no ','"), "{}", err);
+    }
+
+    #[test]
+    fn batch_base64_round_trip() {
+        let (encoded, _, _) = batch("hello world\nZürich\n", "b64encode", &["--url-safe"]);
+        assert_eq!(encoded, "aGVsbG8gd29ybGQ=\nWsO8cmljaA==\n");
+
+        let (out, err, summary) = batch("aGVsbG8gd29ybGQ\n/w==\n", "b64decode", &[]);
+        assert_eq!(out, "hello world\n");
+        assert!(err.contains("not UTF-8"), "{}", err);
+        assert_eq!(summary.failed, 1);
+    }
+
+    #[test]
+    fn codec_flag_switches_encode_and_decode() {
+        let (out, _, _) = batch("hello\n", "encode", &["--codec", "base64url", "--no-pad"]);
+        assert_eq!(out, "aGVsbG8\n");
+        let (out, _, _) = batch("aGVsbG8\n", "decode", &["--codec", "base64url"]);
+        assert_eq!(out, "hello\n");
+        let (out, _, _) = batch("a&b\n", "encode", &["--codec", "html"]);
+        assert_eq!(out, "a&amp;b\n");
+        let (out, _, _) = batch("a b\n", "encode", &[]);
+        assert_eq!(out, "a%20b\n");
+        let (out, _, _) = batch("a b\n", "b64encode", &["--codec", "hex"]);
+        assert_eq!(out, "YSBi\n", "named codec operations ignore --codec");
+    }
+
+    #[test]
+    fn recase_escapes_touches_only_escapes() {
+        let cases = [
+            ("%2f%2F", "%2F%2F", "%2f%2f"),
+            ("/a%c3%A9b?x=%Ab", "/a%C3%A9b?x=%AB", "/a%c3%a9b?x=%ab"),
+            ("2F2f%%2%zz%g1", "2F2f%%2%zz%g1", "2F2f%%2%zz%g1"),
+            ("%%2f", "%%2F", "%%2f"),
+            ("", "", ""),
+        ];
+
+        for (input, upper, lower) in cases {
+            assert_eq!(recase_escapes(input, HexCase::Upper), upper, "{}", input);
+            assert_eq!(recase_escapes(input, HexCase::Lower), lower, "{}", input);
+        }
+        assert!(matches!(recase_escapes("%2F 2f", HexCase::Upper), Cow::Borrowed(_)));
+
+        let (out, _, _) = batch("%2f%2F%C3\n", "recase-escapes", &["--lower-hex"]);
+        assert_eq!(out, "%2f%2f%c3\n");
+    }
+
+    #[test]
+    fn encode_url_leaves_an_encoded_url_unchanged() {
+        let encoded = "https://e.com/a%20b/%C3%A9?q=a%26b%3Dc&r=%2F#x%23y";
+
+        assert_eq!(URLEncoder::new(encoded.to_string()).encode_url(), encoded);
+        let (out, _, _) = batch("https://e.com/a b?q=é\n", "encode", &["--url"]);
+        assert_eq!(out, "https://e.com/a%20b?q=%C3%A9\n");
+    }
+
+    fn form_body(args: &[&str]) -> Result<String, String> {
+        form(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn form_follows_curl_data_urlencode_syntax() {
+        assert_eq!(form_body(&["hello world&x"]).unwrap(), "hello%20world%26x");
+        assert_eq!(form_body(&["=a=b c"]).unwrap(), "a%3Db%20c");
+        assert_eq!(
+            form_body(&["name=John Doe+1", "q=é/~"]).unwrap(),
+            "name=John%20Doe%2B1&q=%C3%A9%2F~"
+        );
+        assert_eq!(form_body(&["a=b@c"]).unwrap(), "a=b%40c");
+        assert_eq!(form_body(&["a b="]).unwrap(), "a b=", "names are not encoded");
+        assert_eq!(form_body(&[]).unwrap(), "");
+        let missing = form_body(&["@/nonexistent/url_encoder-test"]);
+        assert!(missing.unwrap_err().starts_with("cannot read"));
+    }
+
+    #[test]
+    fn form_reads_file_content_byte_for_byte() {
+        let path = std::env::temp_dir().join(format!("url_encoder-form-{}", process::id()));
+        std::fs::write(&path, b"line 1\nline 2\r\n\x00\xFF&=\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let bare = form_body(&[&format!("@{}", path)]);
+        let named = form_body(&[&format!("data@{}", path), "x=1"]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bare.unwrap(), "line%201%0Aline%202%0D%0A%00%FF%26%3D%0A");
+        assert_eq!(named.unwrap(), "data=line%201%0Aline%202%0D%0A%00%FF%26%3D%0A&x=1");
+    }
+
+    fn formbody_of(args: &[&str], flags: &[&str]) -> Result<String, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let flags: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+        formbody(&args, &flags)
+    }
+
+    #[test]
+    fn formbody_form_encodes_pairs() {
+        assert_eq!(
+            formbody_of(&["key1=val1", "key2=hello world", "city=Zürich", "emoji=😀"], &[]).unwrap(),
+            "key1=val1&key2=hello+world&city=Z%C3%BCrich&emoji=%F0%9F%98%80"
+        );
+        assert_eq!(
+            formbody_of(&["empty=", "=novalue", "flag"], &[]).unwrap(),
+            "empty=&=novalue&flag"
+        );
+        assert_eq!(formbody_of(&["a+b=c&d=e"], &[]).unwrap(), "a%2Bb=c%26d%3De");
+        assert_eq!(formbody_of(&[], &[]).unwrap(), "");
+    }
+
+    #[test]
+    fn formbody_keys_can_escape_equals() {
+        assert_eq!(escaped_key_value(r"a\=b=c=d"), ("a=b".to_string(), Some("c=d")));
+        assert_eq!(escaped_key_value(r"a\\=b"), (r"a\".to_string(), Some("b")));
+        assert_eq!(escaped_key_value(r"a\b\=c"), (r"a\b=c".to_string(), None));
+        assert_eq!(escaped_key_value(r"x\"), (r"x\".to_string(), None));
+        assert_eq!(formbody_of(&[r"k\=1=v w"], &[]).unwrap(), "k%3D1=v+w");
+    }
+
+    #[test]
+    fn formbody_from_json_objects() {
+        let json = r#"{"name": "John Doe", "age": 30, "note": null, "é": ""}"#;
+
+        assert_eq!(
+            formbody_of(&[json, r#"{"x": "1"}"#], &["--json"]).unwrap(),
+            "name=John+Doe&age=30&note&%C3%A9=&x=1"
+        );
+        assert!(formbody_of(&["a=b"], &["--json"]).unwrap_err().starts_with("--json:"));
+    }
+
+    #[test]
+    fn batch_summary_aggregates_without_per_line_output() {
+        let mut input = String::new();
+        for i in 0..300 {
+            match i % 3 {
+                0 => input.push_str(&format!("https://a.example.com/{}\n", i)),
+                1 => input.push_str(&format!("http://host{}.test/p\n", i % 90)),
+                _ => input.push_str("https://e.com/?next=javascript:x\n"),
+            }
+        }
+        let lengths: Vec<usize> = input.lines().map(str::len).collect();
+        let (out, _, summary) = batch(&input, "analyze", &["--summary", "--jobs", "3"]);
+
+        assert_eq!(summary.processed, 300);
+        let mean = lengths.iter().sum::<usize>() as f64 / 300.0;
+        assert_eq!(
+            out,
+            format!(
+                "Batch Summary\n=============\nURLs: 300\n\
+                 Length: min {}, max {}, mean {:.2}\n\
+                 Schemes: https (200), http (100)\n\
+                 Top Hosts: a.example.com (100), e.com (100), host1.test (4), host10.test (4), \
+                 host13.test (4), host16.test (4), host19.test (4), host22.test (4), \
+                 host25.test (4), host28.test (4)\n\
+                 Risk Flags: 100 in 100 URL(s)\n",
+                lengths.iter().min().unwrap(),
+                lengths.iter().max().unwrap(),
+                mean
+            )
+        );
+
+        let (out, _, _) = batch("a\nhttps://b.com/\n", "analyze", &["--summary", "--per-url"]);
+        assert_eq!(out.lines().count(), 2 + 7);
+        assert!(out.starts_with("a\tlength=1 "), "{}", out);
+        let flags = ["--summary", "--per-url", "--format", "json"];
+        let (out, err, _) = batch("a\nhttps://b.com/\n", "analyze", &flags);
+        let (lines, _, _) = batch("a\nhttps://b.com/\n", "analyze", &["--format", "json"]);
+        assert!(out.starts_with(&lines), "{}", out);
+        assert!(out[lines.len()..].starts_with("Batch Summary\n"), "{}", out);
+        assert_eq!(err, "");
+        let (out, _, _) = batch("", "analyze", &["--summary"]);
+        assert_eq!(out, "Batch Summary\n=============\nURLs: 0\n");
+    }
+
+    #[test]
+    fn batch_groups_by_registrable_domain() {
+        let input = "\
+            https://www.example.com/a?x=1\n\
+            https://shop.example.com/a?x=1&y=2\n\
+            https://example.com\n\
+            http://example.com/b\n\
+            https://b.example.co.uk/?q\n\
+            https://a.example.co.uk/\n\
+            not a url\n\
+            http://10.0.0.1/x\n\
+            file:///etc/hosts\n\
+            also bad\n";
+        let (table, _, summary) = batch(input, "analyze", &["--group-by", "domain"]);
+
+        assert_eq!(summary.processed, 10);
+        assert_eq!(
+            table,
+            "domain           urls   paths  avg params\n\
+             example.com         4       3        0.75\n\
+             <invalid>           2       0        0.00\n\
+             example.co.uk       2       1        0.50\n\
+             10.0.0.1            1       1        0.00\n\
+             <no host>           1       1        0.00\n"
+        );
+
+        let flags = ["--group-by", "domain", "--json", "--jobs", "4"];
+        let (json, _, _) = batch(input, "analyze", &flags);
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            "{\"domain\":\"example.com\",\"urls\":4,\"distinct_paths\":3,\"avg_query_params\":0.75}"
+        );
+        assert!(lines[1].starts_with("{\"domain\":\"<invalid>\","), "{}", lines[1]);
+
+        let (out, _, _) = batch(input, "analyze", &["--group-by", "domain", "--summary"]);
+        assert!(out.starts_with(&table), "{}", out);
+        assert!(out.ends_with("Risk Flags: 0 in 0 URL(s)\n"), "{}", out);
+    }
+
+    #[test]
+    fn dedupe_keeps_first_of_each_normalized_form() {
+        let input = "\
+            http://EXAMPLE.com:80/a/../b\n\
+            http://example.com/b\n\
+            https://example.com/b\n\
+            http://example.com/%62\n\
+            http://example.com/b?y=2&x=1\n\
+            http://example.com/b?x=1&y=2\n\
+            http://example.com/b#top\n\
+            not a url\n\
+            not a url\n\
+            http://example.com/B\n";
+
+        let (out, _, summary) = batch(input, "dedupe", &[]);
+        assert_eq!(summary.processed, 10);
+        assert_eq!(
+            out,
+            "http://EXAMPLE.com:80/a/../b\n\
+             https://example.com/b\n\
+             http://example.com/b?y=2&x=1\n\
+             http://example.com/b?x=1&y=2\n\
+             http://example.com/b#top\n\
+             not a url\n\
+             http://example.com/B\n"
+        );
+
+        let flags = ["--sort-params", "--strip-fragment", "--jobs", "3"];
+        let (out, _, _) = batch(input, "dedupe", &flags);
+        assert_eq!(
+            out,
+            "http://EXAMPLE.com:80/a/../b\n\
+             https://example.com/b\n\
+             http://example.com/b?y=2&x=1\n\
+             not a url\n\
+             http://example.com/B\n"
+        );
+    }
+
+    #[test]
+    fn dedupe_can_show_duplicate_groups() {
+        let input = "http://a.com/x\nhttp://b.com/\nHTTP://A.COM/./x\nhttp://b.com:80\n\
+                     http://a.com/x\n";
+        let (out, _, _) = batch(input, "dedupe", &["--show-dupes"]);
+
+        assert_eq!(
+            out,
+            "http://a.com/x\n  HTTP://A.COM/./x\n  http://a.com/x\n\
+             http://b.com/\n  http://b.com:80\n"
+        );
+        let (out, _, _) = batch("http://a.com/\nhttp://b.com/\n", "dedupe", &["--show-dupes"]);
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn sort_clusters_sites_and_orders_paths_depth_first() {
+        let input = "\
+            https://www.example.com/b\n\
+            not a url\n\
+            https://shop.example.com/\n\
+            http://EXAMPLE.com/a\n\
+            https://example.org/\n\
+            https://example.com/a/b?x=1\n\
+            https://a.shop.example.com/\n\
+            https://example.com/a/b\n\
+            also not a url\n\
+            https://example.com/a?z=1\n\
+            https://192.0.2.1/\n\
+            https://example.co.uk/\n";
+
+        let (out, _, summary) = batch(input, "sort", &[]);
+        assert_eq!(summary.processed, 12);
+        assert_eq!(
+            out,
+            "https://192.0.2.1/\n\
+             https://example.co.uk/\n\
+             http://EXAMPLE.com/a\n\
+             https://example.com/a?z=1\n\
+             https://example.com/a/b\n\
+             https://example.com/a/b?x=1\n\
+             https://shop.example.com/\n\
+             https://a.shop.example.com/\n\
+             https://www.example.com/b\n\
+             https://example.org/\n\
+             not a url\n\
+             also not a url\n"
+        );
+
+        let (out, _, _) = batch(input, "sort", &["--reverse", "--jobs", "2"]);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "https://example.org/");
+        assert_eq!(lines[9], "https://192.0.2.1/");
+        assert_eq!(lines[10..], ["not a url", "also not a url"]);
+    }
+
+    #[test]
+    fn sort_by_length_and_alpha() {
+        let input = "https://e.com/long/path\nbad\nhttps://E.com/b\nhttps://e.com/\n\
+                     HTTPS://d.com/z\n";
+
+        let (out, _, _) = batch(input, "sort", &["--by", "length"]);
+        assert_eq!(
+            out,
+            "https://e.com/\nhttps://E.com/b\nHTTPS://d.com/z\nhttps://e.com/long/path\nbad\n"
+        );
+        let (out, _, _) = batch(input, "sort", &["--by", "alpha"]);
+        assert_eq!(
+            out,
+            "HTTPS://d.com/z\nhttps://e.com/\nhttps://E.com/b\nhttps://e.com/long/path\nbad\n"
+        );
+
+        // No scheme is assumed, so a schemeless line stays with the unparsed
+        let input = "https://e.com/b\nexample.com/a\nhttps://d.com/\n";
+        for by in ["hierarchy", "length", "alpha"] {
+            let (out, _, _) = batch(input, "sort", &["--by", by, "--reverse"]);
+            assert!(out.ends_with("\nexample.com/a\n"), "{}: {}", by, out);
+        }
+    }
+
+    #[test]
+    fn extract_lists_urls_with_offsets() {
+        assert_eq!(
+            extracted_lines("a https://é.com/ b\nwww.e.com\n", true),
+            ["2\thttps://é.com/", "20\twww.e.com"]
+        );
+        let input = "x https://a.com y\nnothing here\nwww.b.com\n";
+        let (out, _, summary) = batch(input, "extract", &[]);
+        assert_eq!(summary.processed, 3);
+        assert_eq!(out, "https://a.com\nwww.b.com\n");
+    }
+
+    #[test]
+    fn format_flag_shares_json_and_csv_output() {
+        let input = "https://e.com/a?token=s3cret\n";
+        let output = |flags: &[&str]| batch(input, "analyze", flags).0;
+        assert_eq!(output(&["--format", "json"]), output(&["--json"]));
+        assert_eq!(output(&["--format", "csv"]), output(&["--csv"]));
+
+        let out = output(&["--format", "markdown", "--redact"]);
+        assert!(out.starts_with("## URL Analysis Report\n"));
+        assert!(out.contains("| Query | token="));
+        assert!(!out.contains("s3cret"));
+    }
+
+    fn assert_balanced_html(html: &str) {
+        let mut open: Vec<&str> = Vec::new();
+        let mut rest = html;
+
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start + 1..];
+            let end = rest.find('>').expect("unterminated tag");
+            let tag = &rest[..end];
+            rest = &rest[end + 1..];
+            if tag.starts_with('!') {
+                continue;
+            }
+            let name = tag.trim_start_matches('/').split(' ').next().unwrap();
+            if tag.starts_with('/') {
+                assert_eq!(open.pop(), Some(name), "unbalanced </{}>", name);
+            } else if !["meta", "br"].contains(&name) {
+                open.push(name);
+                if name == "style" || name == "script" {
+                    let close = format!("</{}>", name);
+                    rest = &rest[rest.find(&close).expect("unclosed raw text element")..];
+                }
+            }
+        }
+        assert_eq!(open, Vec::<&str>::new());
+    }
+
+    #[test]
+    fn html_report_escapes_urls_and_balances_tags() {
+        let input = "https://e.com/?q=<script>alert('x')</script>\n\
+                     https://shop.example/login?next=https://evil.com/\"onload=\n\
+                     https://e.com/a\n";
+        let (out, _, summary) = batch(input, "analyze", &["--format", "html", "--jobs", "2"]);
+
+        assert_eq!(summary.processed, 3);
+        assert!(out.starts_with("<!DOCTYPE html>\n"));
+        assert_eq!(out.matches("<script>").count(), 1);
+        assert!(out.contains(
+            "<code>https://e.com/?q=&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;</code>"
+        ));
+        assert!(out.contains("evil.com/&quot;onload="));
+        assert_eq!(out.matches("<tr class=\"risky\">").count(), 1);
+        assert!(out.contains("<dt>URLs</dt><dd>3</dd>"));
+        assert!(!out.contains("http://") && !out.contains("src="));
+        assert_balanced_html(&out);
+        let (summarized, _, _) = batch(input, "analyze", &["--format", "html", "--summary"]);
+        assert_eq!(summarized, out);
+    }
+
+    #[test]
+    fn short_output_flag_takes_a_value() {
+        let args: Vec<String> = ["analyze", "-o", "r.html", "--batch", "u.txt"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let (positionals, flags) = split_args(&args).unwrap();
+        assert_eq!(positionals, ["analyze"]);
+        assert_eq!(flag_value(&flags, "--output"), Some("r.html"));
+    }
+
+    #[test]
+    fn batch_yaml_is_a_document_stream() {
+        let input = "https://a.com/\nhttps://b.com/\n";
+        let (out, _, _) = batch(input, "analyze", &["--format", "yaml"]);
+        assert_eq!(out.matches("---\n").count(), 2);
+        assert!(out.contains("\nrisk_flags: []\n---\nanalysis:\n"));
+        assert!(out.ends_with("risk_flags: []\n"));
+    }
+
+    fn output_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("url_encoder-{}-{}", name, process::id()))
+    }
+
+    fn run_to(path: &Path, input: &str, operation: &str, flags: &[&str]) -> Result<(), ExitCode> {
+        let flags: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+        let options = CliOptions::parse(&flags);
+        let mut out = Output::open(path, options.append_output).unwrap();
+        run_operation(input, operation, &options, &mut out)?;
+        out.finish().map_err(ExitCode::from)
+    }
+
+    #[test]
+    fn output_replaces_a_file_only_on_success() {
+        let path = output_path("replace");
+        let temp_files = || {
+            let dir = std::fs::read_dir(std::env::temp_dir()).unwrap();
+            let prefix = format!(".{}.", path.file_name().unwrap().to_string_lossy());
+            dir.filter(|entry| {
+                entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&prefix)
+            })
+            .count()
+        };
+        std::fs::write(&path, "old\n").unwrap();
+
+        assert_eq!(run_to(&path, "%zz", "decode", &[]), Err(ExitCode(PARSE_EXIT_CODE)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+        let mut out = Output::open(&path, false).unwrap();
+        out.write_all(b"half of a res").unwrap();
+        drop(out);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+        assert_eq!(temp_files(), 0);
+
+        assert_eq!(run_to(&path, "a b", "encode", &[]), Ok(()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Encoded: a%20b\n");
+        assert_eq!(temp_files(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn append_output_accumulates_across_runs() {
+        let path = output_path("append");
+        let _ = std::fs::remove_file(&path);
+        let flags = ["--append-output", "ignored-by-run_to"];
+
+        assert_eq!(run_to(&path, "a b", "encode", &flags), Ok(()));
+        assert_eq!(run_to(&path, "c%2Fd", "decode", &flags), Ok(()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Encoded: a%20b\nDecoded: c/d\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn output_flags_choose_the_file_and_mode() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            let (_, flags) = split_args(&args).unwrap();
+            let options = CliOptions::parse(&flags);
+            (options.output, options.append_output)
+        };
+
+        assert_eq!(parse(&["x", "-o", "r.txt"]), (Some("r.txt".to_string()), false));
+        assert_eq!(parse(&["x", "--append-output", "log"]), (Some("log".to_string()), true));
+        assert_eq!(parse(&["x", "--append", "k=v"]), (None, false));
+    }
+
+    #[test]
+    fn output_keeps_a_complete_result_with_a_verdict_exit_code() {
+        let path = output_path("verdict");
+        let flags = ["--fail-on-risk"];
+        let flags: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+        let options = CliOptions::parse(&flags);
+        let mut out = Output::open(&path, false).unwrap();
+
+        let input = "https://e.com/?next=https://evil.com";
+        let result = run_operation(input, "analyze", &options, &mut out);
+        assert_eq!(result, Err(ExitCode(FAILED_CHECK_EXIT_CODE)));
+        assert!(is_complete(&result));
+        assert!(!is_complete(&Err(ExitCode(PARSE_EXIT_CODE))));
+        out.finish().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.contains("Warning: possible open redirect"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn batch_analyze_checks_each_line_against_the_limits() {
+        let input = "https://e.com/a\nhttps://e.com/aaaaaaaaaa\n";
+        let limited = ["--max-length", "20", "--fail-on-limit"];
+        let (out, err, summary) = batch(input, "analyze", &limited);
+
+        assert!(out.starts_with("https://e.com/a\tlength=15 "), "{}", out);
+        assert_eq!(out.lines().count(), 1);
+        assert_eq!(err, "line 2: Error: over a length limit: URL is 24 bytes (limit 20)\n");
+        assert_eq!((summary.processed, summary.failed), (2, 1));
+
+        let (out, _, summary) = batch(input, "analyze", &["--max-length", "20"]);
+        assert_eq!((out.lines().count(), summary.failed), (2, 0));
+
+        let (out, err, summary) = batch(input, "analyze", &["--summary", "--fail-on-limit"]);
+        assert!(out.contains("URLs: 2\n"), "{}", out);
+        assert_eq!((err.as_str(), summary.failed), ("", 0));
+        let (_, err, summary) =
+            batch(input, "analyze", &["--summary", "--max-length", "20", "--fail-on-limit"]);
+        assert!(err.starts_with("line 2: Error: over a length limit"), "{}", err);
+        assert_eq!(summary.failed, 1);
+
+        let risky = "https://e.com/?next=https://evil.com\n";
+        let (out, err, _) = batch(risky, "analyze", &["--format", "json", "--fail-on-risk"]);
+        assert_eq!(out, "");
+        assert!(err.starts_with("line 1: Error: possible open redirect"), "{}", err);
+    }
+
+    #[test]
+    fn null_records_keep_newlines_through_a_round_trip() {
+        let (out, err, summary) = batch("a%0Ab\0\0c%0Dd\0#e\r\0", "decode", &["--null"]);
+        assert_eq!(out, "a\nb\0c\rd\0#e\r\0");
+        assert_eq!((err.as_str(), summary.processed), ("", 3));
+
+        let records = "line one\nline two\0cr\r\0\r\n\0";
+        let (encoded, _, _) = batch(records, "encode", &["--null"]);
+        assert!(!encoded.contains('\n') && !encoded.contains('\r'), "{:?}", encoded);
+        let (decoded, _, _) = batch(&encoded, "decode", &["--null"]);
+        assert_eq!(decoded, records);
+    }
+
+    #[test]
+    fn null_records_report_errors_on_newline_terminated_lines() {
+        let (out, err, summary) = batch("a\0bad%zz\0", "decode", &["--null"]);
+        assert_eq!(out, "a\0");
+        assert_eq!(err, "line 2: Error: Invalid hex sequence %zz at byte offset 3\n");
+        assert_eq!(summary.failed, 1);
+
+        let (out, _, _) = batch("see https://a.com and https://b.com\0", "extract", &["--null"]);
+        assert_eq!(out, "https://a.com\0https://b.com\0");
+        let args: Vec<String> = ["decode", "-0"].iter().map(|a| a.to_string()).collect();
+        assert_eq!(split_args(&args).unwrap().1, ["--null"]);
+    }
+
+    #[test]
+    fn split_args_takes_flags_anywhere_until_the_terminator() {
+        let (positionals, flags) = split_args(&args(&["--json", "analyze", "-q", "u"])).unwrap();
+        assert_eq!((positionals, flags), (args(&["analyze", "u"]), args(&["--json", "--quiet"])));
+
+        let split = split_args(&args(&["decode", "--format=csv", "--", "-v", "--json"])).unwrap();
+        assert_eq!(split, (args(&["decode", "-v", "--json"]), args(&["--format", "csv"])));
+        let (positionals, _) = split_args(&args(&["-5%25", "decode", "-"])).unwrap();
+        assert_eq!(positionals, ["-5%25", "decode", "-"]);
+
+        assert_eq!(split_args(&args(&["encode", "--file"])), Err("--file needs a value".into()));
+        assert_eq!(split_args(&args(&["-o"])), Err("-o needs a value".into()));
+        assert_eq!(split_args(&args(&["--json=1"])), Err("--json does not take a value".into()));
+        assert_eq!(
+            split_args(&args(&["-x", "encode"])),
+            Err("Unknown option: -x. Put -- before an input that starts with -".into())
+        );
+    }
+
+    #[test]
+    fn check_flags_suggests_the_nearest_known_flag() {
+        assert_eq!(check_flags(&args(&["--json", "--prefix", "--bogus"]), OPERATION_FLAGS), Ok(()));
+        assert_eq!(
+            check_flags(&args(&["--jsno"]), OPERATION_FLAGS),
+            Err("Unknown flag: --jsno. Did you mean --json?".into())
+        );
+        assert_eq!(
+            check_flags(&args(&["--verbos"]), &[]),
+            Err("Unknown flag: --verbos. Did you mean --verbose?".into())
+        );
+        assert_eq!(
+            check_flags(&args(&["--frobnicate"]), OPERATION_FLAGS),
+            Err("Unknown flag: --frobnicate. See --help for the flags".into())
+        );
+
+        assert_eq!(check_operation_flags(&args(&["--url", "--quiet"]), "encode"), Ok(()));
+        assert_eq!(check_operation_flags(&args(&["--batch", "urls.txt"]), "decode"), Ok(()));
+        let risk = check_operation_flags(&args(&["--fail-on-risk"]), "encode").unwrap_err();
+        assert!(risk.starts_with("encode does not take --fail-on-risk."), "{}", risk);
+        let group = check_operation_flags(&args(&["--group"]), "decode").unwrap_err();
+        assert!(group.starts_with("decode does not take --group."), "{}", group);
+
+        let equiv = SUBCOMMANDS.iter().find(|(name, _)| *name == "equiv").unwrap().1;
+        assert_eq!(check_flags(&args(&["--ignore-fragment", "--quiet"]), equiv), Ok(()));
+        assert!(check_flags(&args(&["--by", "length"]), equiv).is_err());
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn input_and_operation_in_either_order() {
+        let named = |positionals: &[&str], piped: bool| {
+            let positionals = args(positionals);
+            let owned = |name: Option<&str>| name.map(str::to_string);
+            input_and_operation(&positionals, false, piped)
+                .map(|(input, operation)| (owned(input), owned(operation)))
+        };
+        let pair = |input: &str, operation: &str| Ok((Some(input.into()), Some(operation.into())));
+
+        assert_eq!(named(&["a%20b", "decode"], false), pair("a%20b", "decode"));
+        assert_eq!(named(&["decode", "a%20b"], false), pair("a%20b", "decode"));
+        assert_eq!(named(&["encode", "decode"], false), pair("encode", "decode"));
+        assert_eq!(named(&["decode"], true), pair("-", "decode"));
+        assert_eq!(named(&["text"], false), Ok((Some("text".into()), None)));
+        assert_eq!(named(&[], true), Ok((Some("-".into()), None)));
+
+        let sources = "Give a URL or text, - to read stdin, or --file or --batch <path>";
+        assert_eq!(named(&[], false), Err(format!("No input. {}", sources)));
+        assert_eq!(named(&["decode"], false), Err(format!("No input for decode. {}", sources)));
+        assert_eq!(named(&["a", "encode", "b"], false), Err("Unexpected argument: b".into()));
+        assert_eq!(
+            named(&["jion", "a", "b"], false),
+            Err("Unknown command: jion. Did you mean join?".into())
+        );
+
+        let positionals = args(&["decode", "u"]);
+        assert_eq!(
+            input_and_operation(&positionals, true, false),
+            Err("Unexpected argument: u".into())
+        );
+        let message = unknown_operation("decod");
+        assert!(message.starts_with("Unknown operation: decod. Did you mean decode? "));
+    }
+
+    #[test]
+    fn help_lists_every_command_and_flag() {
+        let overview = overview_help();
+        for name in OPERATIONS.iter().chain(SUBCOMMANDS.iter().map(|(name, _)| name)) {
+            let command = CommandHelp::find(name).expect("every command has help");
+            let line = format!("\n  {:width$}  {}\n", name, command.summary, width = 14);
+            assert!(overview.contains(&line), "{}", name);
+        }
+        assert_eq!(COMMAND_HELP.len(), OPERATIONS.len() + SUBCOMMANDS.len());
+        assert!(FLAG_HELP.iter().all(|flag| flag.help.len() + flag.name.len() < 80));
+
+        let documented: HashSet<&str> = COMMAND_HELP
+            .iter()
+            .filter(|command| command.is_operation())
+            .flat_map(|command| command.flags.iter().chain(INPUT_OUTPUT_FLAGS))
+            .copied()
+            .collect();
+        for flag in OPERATION_FLAGS {
+            assert!(documented.contains(flag), "{} is not in any operation's help", flag);
+        }
+    }
+
+    #[test]
+    fn flag_tables_match_the_help() {
+        for flag in FLAG_HELP {
+            let takes_value = VALUE_FLAGS.contains(&flag.name);
+            assert_eq!(flag.value.is_some(), takes_value, "{} in VALUE_FLAGS", flag.name);
+        }
+        for name in VALUE_FLAGS {
+            assert!(FLAG_HELP.iter().any(|flag| flag.name == *name), "{} has no help", name);
+        }
+
+        let mut documented: Vec<&str> = COMMAND_HELP
+            .iter()
+            .filter(|command| command.is_operation())
+            .flat_map(|command| command.accepted_flags())
+            .collect();
+        documented.sort_unstable();
+        documented.dedup();
+        assert_eq!(OPERATION_FLAGS, &documented[..]);
+
+        let subcommands: Vec<&str> = SUBCOMMANDS.iter().map(|(name, _)| *name).collect();
+        let helped: Vec<&str> = COMMAND_HELP
+            .iter()
+            .filter(|command| !command.is_operation())
+            .map(|command| command.name)
+            .collect();
+        assert_eq!(subcommands, helped);
+        for (name, flags) in SUBCOMMANDS {
+            let mut flags = flags.to_vec();
+            let mut helped = CommandHelp::find(name).unwrap().flags.to_vec();
+            flags.sort_unstable();
+            helped.sort_unstable();
+            assert_eq!(flags, helped, "{}", name);
+        }
+    }
+
+    #[test]
+    fn every_flag_in_help_is_accepted() {
+        for command in COMMAND_HELP {
+            let help = command.render();
+            let accepted = match SUBCOMMANDS.iter().find(|(name, _)| *name == command.name) {
+                Some((_, flags)) => flags.to_vec(),
+                None => command.accepted_flags(),
+            };
+            let mut flags: Vec<&str> = command.flags.to_vec();
+            if command.is_operation() {
+                flags.extend(INPUT_OUTPUT_FLAGS);
+            }
+            for name in flags.into_iter().chain(GLOBAL_FLAGS.iter().copied()) {
+                let flag = flag_help(command.name, name).unwrap();
+                assert!(help.contains(&flag.synopsis()), "{} {}", command.name, name);
+                let args = match flag.value {
+                    Some(_) => args(&[name, "x"]),
+                    None => args(&[name]),
+                };
+                let (_, split) = split_args(&args).unwrap();
+                assert_eq!(check_flags(&split, &accepted), Ok(()), "{} {}", command.name, name);
+                if command.is_operation() {
+                    assert_eq!(check_operation_flags(&split, command.name), Ok(()), "{}", name);
+                }
+                if let Some(short) = flag.short {
+                    let short_args = args.iter().skip(1).fold(vec![short.to_string()], |mut a, v| {
+                        a.push(v.clone());
+                        a
+                    });
+                    assert_eq!(split_args(&short_args).unwrap().1, split, "{}", short);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn command_help_shows_defaults_and_examples() {
+        let decode = CommandHelp::find("decode").unwrap().render();
+        assert!(decode.starts_with("url_encoder decode: Decode percent-escapes\n\n"));
+        let max_depth = decode.lines().find(|line| line.starts_with("      --max-depth <n> "));
+        assert!(max_depth.unwrap().ends_with("  Most passes --recursive makes (default: 5)"));
+        assert!(decode.contains("\n  -o, --output <path>"));
+        assert!(decode.contains("\nExamples:\n  url_encoder decode 'caf%C3%A9'\n"));
+
+        let formbody = CommandHelp::find("formbody").unwrap().render();
+        assert!(formbody.contains("--json  Take each argument as a flat JSON object of pairs\n"));
+        assert!(!formbody.contains("Input and output:"));
+        let version = version_text();
+        assert!(version.starts_with("url_encoder ") && version.contains("\nfeatures: std"));
+        let listed = version.split_once("\nfeatures: ").unwrap().1;
+        let listed: Vec<&str> = listed.split(", ").collect();
+        for (name, enabled) in FEATURES {
+            assert_eq!(listed.contains(&name), enabled, "{}", name);
+        }
+    }
+
+    #[test]
+    fn flag_choices_are_accepted() {
+        for flag in FLAG_HELP.iter().filter(|flag| !flag.choices.is_empty()) {
+            for choice in flag.choices {
+                let accepted = match flag.name {
+                    "--color" => style::ColorChoice::from_name(choice).is_some(),
+                    "--component" => Component::from_name(choice).is_some(),
+                    "--codec" => codec(choice).is_some(),
+                    "--format" => OutputFormat::from_name(choice).is_some(),
+                    "--group-by" => *choice == "domain",
+                    "--on-conflict" => MergePolicy::from_name(choice).is_some(),
+                    "--hash" => CacheKeyHash::from_name(choice).is_some(),
+                    "--by" => SortOrder::from_name(choice).is_some(),
+                    "--op" => OPERATIONS.contains(choice),
+                    _ => panic!("{} has choices no test checks", flag.name),
+                };
+                assert!(accepted, "{} {}", flag.name, choice);
+            }
+        }
+    }
+
+    #[test]
+    fn bash_completions_cover_commands_flags_and_choices() {
+        let script = completions(&args(&["bash"])).unwrap();
+        let names: Vec<&str> = COMMAND_HELP.iter().map(|command| command.name).collect();
+
+        assert!(script.contains(&format!("            {}) command=", names.join("|"))));
+        for name in OPERATIONS.iter().chain(SUBCOMMANDS.iter().map(|(name, _)| name)) {
+            assert!(script.contains(&format!("\n        {}) flags=\"", name)), "{}", name);
+        }
+        assert!(script.contains(
+            "\n        --format) COMPREPLY=($(compgen -W \"text json csv markdown html yaml\" \
+             -- \"$cur\")); return ;;\n"
+        ));
+        assert!(script.contains("|-o|--output|--append-output|"));
+        assert!(script.contains("\n        join) flags=\"--before-query -h --help -V --version"));
+        assert!(script.ends_with("\ncomplete -F _url_encoder url_encoder\n"));
+
+        if let Ok(mut bash) = process::Command::new("bash")
+            .arg("-n")
+            .stdin(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+        {
+            bash.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+            let checked = bash.wait_with_output().unwrap();
+            assert!(checked.status.success(), "{}", String::from_utf8_lossy(&checked.stderr));
+        }
+    }
+
+    #[test]
+    fn zsh_completions_escape_descriptions() {
+        let script = completions(&args(&["zsh"])).unwrap();
+
+        assert!(script.starts_with("#compdef url_encoder\n"));
+        assert!(script.contains("\n        'to-file-url:Turn a path into a file: URL'\n"));
+        assert!(script.contains(
+            "'*--keep-default-port[Keep a port equal to the scheme'\\''s default]'"
+        ));
+        assert!(script.contains(
+            "'*--format=[text, json, csv, markdown, html or yaml]:name:(text json csv markdown \
+             html yaml)'"
+        ));
+        assert!(
+            script.contains("'*-o+[Write results to a file, replaced once complete]:path:_files'")
+        );
+        assert!(script.contains("'*--set=[Set a query parameter, replacing its values]:k=v: '"));
+        assert!(script.contains("        (completions)\n            _arguments"));
+        assert!(script.contains("'*:shell:(bash zsh fish)'"));
+    }
+
+    #[test]
+    fn fish_completions_quote_each_description() {
+        let script = completions(&args(&["fish"])).unwrap();
+        let line = |flag: &str| -> &str {
+            script.lines().find(|line| line.contains(&format!(" -l {} ", flag))).unwrap()
+        };
+
+        assert!(script.lines().skip(1).all(|line| line.starts_with("complete -c url_encoder ")));
+        assert!(line("no-pad").ends_with(" -l no-pad -d 'Leave out the = padding'"));
+        assert!(line("set").ends_with(" -x -d 'Set a query parameter, replacing its values'"));
+        let keep_port = line("keep-default-port");
+        assert!(keep_port.ends_with(" -d 'Keep a port equal to the scheme\\'s default'"));
+        assert_eq!(
+            line("output"),
+            "complete -c url_encoder -n '__fish_use_subcommand; or __fish_seen_subcommand_from \
+             encode decode analyze params rewrite clean normalize validate check to-file-url \
+             from-file-url data-decode b64encode b64decode hexencode hexdecode html-encode \
+             html-decode recase-escapes dedupe cache-key anonymize sort extract' -s o -l output \
+             -r -F -d 'Write results to a file, replaced once complete'"
+        );
+        let color = line("color");
+        assert!(color.starts_with("complete -c url_encoder -l color -x -a 'auto always never' "));
+        let json: Vec<&str> = script.lines().filter(|line| line.contains(" -l json ")).collect();
+        assert_eq!(json.len(), 3);
+        assert!(json[1].contains("'__fish_seen_subcommand_from formbody' -l json -d 'Take each"));
+        assert!(json[2].contains("'__fish_seen_subcommand_from bench' -l json -d 'Print the"));
+    }
+
+    fn repl(script: &str) -> String {
+        let mut out = Vec::new();
+        Repl::new(&[]).unwrap().run(script.as_bytes(), &mut out, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn repl_keeps_history() {
+        let script = ":mode decode\na%2Fb\n!!\n!1\nc%3D\n:history\n";
+        assert_eq!(
+            repl(script),
+            "Decoded: a/b\na%2Fb\nDecoded: a/b\n:mode decode\nDecoded: c=\n\
+             \x20  1  :mode decode\n   2  a%2Fb\n   3  a%2Fb\n   4  :mode decode\n   5  c%3D\n   \
+             6  :history\n"
+        );
+        assert_eq!(repl("!9\n!x\n"), "Encoded: %21x\n");
+    }
+
+    #[test]
+    fn repl_set_checks_flags_and_values() {
+        let mut repl = Repl::new(&[]).unwrap();
+        let mut out = Vec::new();
+        let mut set = |command: &str| repl.command(command, &mut out).map(|_| repl.flags.join(" "));
+
+        assert_eq!(set("set --max-depth 3"), Ok("--max-depth 3".to_string()));
+        assert_eq!(set("set recursive on"), Ok("--max-depth 3 --recursive".to_string()));
+        assert_eq!(set("set max-depth 2"), Ok("--recursive --max-depth 2".to_string()));
+        assert_eq!(
+            set("set max-depth x"),
+            Err("Invalid --max-depth: expected a non-negative number".into())
+        );
+        assert_eq!(set("set format yml"), Ok("--recursive --max-depth 2 --format yml".to_string()));
+        assert_eq!(
+            set("set format bogus").unwrap_err(),
+            "Unknown --format: bogus. Use 'text', 'json', 'csv', 'markdown', 'html', or 'yaml'"
+        );
+        assert_eq!(set("set lenient yes"), Err("--lenient is a switch; use on or off".into()));
+        assert_eq!(set("set batch urls.txt"), Err("--batch is not available in the repl".into()));
+        assert_eq!(
+            set("set lenint on"),
+            Err("Unknown flag: --lenint. Did you mean --lenient?".into())
+        );
+        let needs_value = "format needs a value; use on or off for a switch";
+        assert_eq!(set("set format"), Err(needs_value.into()));
+        assert_eq!(set("mode decod"), Err(unknown_operation("decod")));
+        assert_eq!(set("frob"), Err("Unknown command: :frob. Type :help for the commands".into()));
+        assert_eq!(set("reset"), Ok(String::new()));
+
+        let mut repl = Repl::new(&args(&["--verbose", "--max-depth", "2", "--form"])).unwrap();
+        assert_eq!(repl.flags, args(&["--max-depth", "2", "--form"]));
+        assert_eq!((repl.options.max_depth, repl.options.decode.verbosity), (2, 1));
+        repl.command("reset", &mut Vec::new()).unwrap();
+        assert_eq!((repl.options.max_depth, repl.options.decode.verbosity), (5, 1));
+        let output = Repl::new(&args(&["--output", "x"])).err();
+        assert_eq!(output, Some("--output is not available in the repl".into()));
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    const ALWAYS: ColorChoice = ColorChoice::Always;
+
+    #[test]
+    fn color_choice_honors_terminal_and_no_color() {
+        let colored =
+            |choice, tty, no_color| Styler::new(choice, tty, no_color).report("Warning: x");
+        let escape = |text: String| text.contains('\x1b');
+
+        assert!(escape(colored(ALWAYS, false, true)));
+        assert!(!escape(colored(ColorChoice::Never, true, false)));
+        assert!(escape(colored(ColorChoice::Auto, true, false)));
+        assert!(!escape(colored(ColorChoice::Auto, false, false)));
+        assert!(!escape(colored(ColorChoice::Auto, true, true)));
+        assert_eq!(ColorChoice::from_name("sometimes"), None);
+    }
+
+    #[test]
+    fn styled_report_colors_headings_and_warnings() {
+        let report = URLEncoder::new("https://u:p@e.com/?a=1&a=2".to_string()).analyze().report();
+        let never = Styler::new(ColorChoice::Never, true, false);
+        let always = Styler::new(ALWAYS, false, false);
+
+        assert_eq!(never.report(&report), report);
+        let styled = always.report(&report);
+        assert!(styled.starts_with("\x1b[1mURL Analysis Report\x1b[0m\n\x1b[1m===="));
+        assert!(styled.contains("\n\x1b[31mWarning: URL contains credentials"));
+        assert!(styled.contains("\nOriginal Length: 26\n"));
+        let plain = ["\x1b[1m", "\x1b[31m", "\x1b[0m"]
+            .iter()
+            .fold(styled.clone(), |text, code| text.replace(code, ""));
+        assert_eq!(plain, report);
+        assert_eq!(always.field("Host: e.com"), "\x1b[2mHost\x1b[0m: e.com");
+        assert_eq!(never.field("Host: e.com"), "Host: e.com");
+    }
+
+    #[test]
+    fn styled_diff_and_params() {
+        let always = Styler::new(ALWAYS, false, false);
+        let never = Styler::new(ColorChoice::Never, false, false);
+
+        assert_eq!(always.diff_line("- host: a"), "\x1b[31m- host: a\x1b[0m");
+        assert_eq!(always.diff_line("+ host: b"), "\x1b[32m+ host: b\x1b[0m");
+        assert_eq!(never.diff_line("+ host: b"), "+ host: b");
+        assert_eq!(always.param_line("a = 1"), "\x1b[1ma\x1b[0m = 1");
+        assert_eq!(always.param_line("tag: [a, b]"), "\x1b[1mtag\x1b[0m: [a, b]");
+        assert_eq!(always.param_line("flag"), "\x1b[1mflag\x1b[0m");
+        assert_eq!(never.param_line("a = 1"), "a = 1");
+    }
+
+    #[test]
+    fn styled_encoding_highlights_changed_characters() {
+        let always = Styler::new(ALWAYS, false, false);
+        let highlight = |text: &str| format!("\x1b[1;35m{}\x1b[0m", text);
+
+        assert_eq!(
+            always.changes("a b%20é", "a%20b%20%C3%A9"),
+            format!("a{}b%20{}", highlight("%20"), highlight("%C3%A9"))
+        );
+        assert_eq!(always.changes("a b", "a+b"), format!("a{}b", highlight("+")));
+        assert_eq!(always.changes("x", "%78%"), format!("{}{}", highlight("%78"), highlight("%")));
+        assert_eq!(Styler::new(ColorChoice::Never, true, false).changes("a b", "a%20b"), "a%20b");
+    }
+
+    #[test]
+    fn repl_applies_the_mode_to_each_line() {
+        let script = "a b\n:mode decode\na%20b\n\n:mode analyze\n:set format json\n:mode\n\
+                      :set\n:set format off\n:set\n:mode normalize\nHTTP://E.com:80\n";
+        assert_eq!(
+            repl(script),
+            "Encoded: a%20b\nDecoded: a b\nanalyze\n--format json\n\nhttp://e.com/\n"
+        );
+
+        let script = ":mode decode\n:set lenient on\n100%\n:set lenient off\n5%25\n";
+        assert_eq!(repl(script), "Decoded: 100%\nDecoded: 5%\n");
+        assert_eq!(repl("x\r\n:quit\ny\n"), "Encoded: x\n");
+    }
+
+    #[test]
+    fn batch_check_fails_lines_that_do_not_round_trip() {
+        let (out, err, summary) = batch("a%20b\na+b\n100%\n", "check", &[]);
+
+        assert_eq!(summary, BatchSummary { processed: 3, failed: 1, ..BatchSummary::default() });
+        assert_eq!(
+            out,
+            "encode then decode: ok\tdecode then encode: ok\n\
+             encode then decode: ok\tdecode then encode: skipped, the input does not decode: \
+             Truncated percent escape % at byte offset 3\n"
+        );
+        assert!(err.contains("decode then encode: differs: a+b -> a%20b"), "{}", err);
+        let (_, _, summary) = batch("a+b\n", "check", &["--form"]);
+        assert_eq!(summary.failed, 0);
+    }
+
+    fn bench_report(corpus: &str, flags: &[&str]) -> Result<BenchReport, String> {
+        let path = output_path("bench-corpus");
+        std::fs::write(&path, corpus).unwrap();
+        let mut flags: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+        flags.extend(["--file".to_string(), path.display().to_string()]);
+        let report = bench(&[], &flags);
+        std::fs::remove_file(&path).unwrap();
+        report
+    }
+
+    #[test]
+    fn bench_times_each_iteration_of_the_corpus() {
+        let corpus = "https://example.com/a b?q=1\n\nhttps://e.com/%zz\n";
+        let report = bench_report(corpus, &["--op", "decode", "--iterations", "2"]).unwrap();
+
+        assert_eq!((report.operation.as_str(), report.urls, report.bytes), ("decode", 2, 44));
+        assert_eq!(report.times.len(), 2);
+        let [min, median, max] = report.min_median_max();
+        assert!(Duration::ZERO < min && min <= median && median <= max);
+        let [seconds, megabytes, urls] = report.stats();
+        assert!(seconds[0] > 0.0 && seconds[0] <= seconds[1] && seconds[1] <= seconds[2]);
+        assert!(megabytes[0] > 0.0 && megabytes[0] <= megabytes[1] && megabytes[1] <= megabytes[2]);
+        assert!(urls[0] > 0.0 && urls[0] <= urls[2] && urls[2].is_finite());
+
+        let json = report.json();
+        assert!(json.starts_with(
+            r#"{"operation":"decode","urls":2,"bytes":44,"iterations":2,"warmup_iterations":1,"#
+        ));
+        for key in [r#""seconds":{"min":"#, r#""mb_per_second":{"#, r#""urls_per_second":{"#] {
+            assert!(json.contains(key), "{}", key);
+        }
+        let text = report.text();
+        let header = "bench decode: 2 URLs (44 bytes), 2 iterations after 1 warm-up\n";
+        assert!(text.starts_with(header));
+        let rows: Vec<Option<&str>> =
+            text.lines().map(|line| line.split_whitespace().next()).collect();
+        assert_eq!(rows, [Some("bench"), Some("min"), Some("ms"), Some("MB/s"), Some("URLs/s")]);
+        assert!(!text.contains("example.com"));
+    }
+
+    #[test]
+    fn bench_rejects_bad_arguments() {
+        assert_eq!(bench(&[], &[]).unwrap_err(), "bench needs a corpus: --file <path>");
+        let unknown = bench_report("x\n", &["--op", "nope"]).unwrap_err();
+        assert!(unknown.starts_with("Unknown operation"));
+        assert_eq!(
+            bench_report("x\n", &["--iterations", "0"]).unwrap_err(),
+            "Invalid --iterations: expected a positive number"
+        );
+        assert!(bench_report("\n\n", &[]).unwrap_err().ends_with(" has no URLs"));
+    }
+
+    #[test]
+    fn mapped_batch_matches_buffered_reads() {
+        let path = output_path("mapped");
+        let input = "a%20b\r\n# comment\r\n\r\nbad%zz\nc+d\r\n\ne%41";
+        std::fs::write(&path, input).unwrap();
+        let file = File::open(&path).unwrap();
+        let map = mapped::Mapped::new(&file).unwrap();
+        let options = CliOptions::parse(&[]);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let records = map.bytes().split(|&b| b == b'\n').map(|record| Ok(Cow::Borrowed(record)));
+        let summary = run_batch_records(records, &mut out, &mut err, "decode", &options).unwrap();
+
+        let mapped = (String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap(), summary);
+        assert_eq!(mapped.0, "a b\nc d\neA\n");
+        assert_eq!(mapped.1, "line 4: Error: Invalid hex sequence %zz at byte offset 3\n");
+        assert_eq!(mapped, batch(input, "decode", &[]));
+        std::fs::write(&path, "").unwrap();
+        assert!(mapped::Mapped::new(&File::open(&path).unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn expand_takes_lists_from_commas_and_json() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let json = args(&["--vars-json", r#"{"id": 7, "tags": ["a b", "c"], "page": 1}"#]);
+
+        assert_eq!(
+            expand(&args(&["/users/{id}/posts{?page,limit}", "id=42", "page=2"]), &[]).unwrap(),
+            "/users/42/posts?page=2"
+        );
+        assert_eq!(
+            expand(&args(&["/u/{id}{/path*}{?tags*,page}", "path=x,y z"]), &json).unwrap(),
+            "/u/7/x/y%20z?tags=a%20b&tags=c&page=1"
+        );
+        assert_eq!(expand(&args(&["{id}", "id=1"]), &json).unwrap(), "1");
+        let strict = args(&["--strict"]);
+        assert_eq!(
+            expand(&args(&["{?page,limit}", "page=2"]), &strict),
+            Err("Template variable 'limit' is undefined".to_string())
+        );
+        let missing_value = Err("Expected name=value, got: a".to_string());
+        assert_eq!(expand(&args(&["{a}", "a"]), &[]), missing_value);
+        assert!(expand(&[], &[]).is_err());
+    }
+
+    #[test]
+    fn rewrite_component_flags_apply_in_order() {
+        let flags = [
+            "--scheme",
+            "https",
+            "--host",
+            r"s/^www\.//",
+            "--set",
+            "a=2",
+            "--path",
+            r"s/index\.html$//",
+            "--fragment",
+            "",
+        ];
+        let input = "http://www.e.com/docs/index.html?a=1#top\nhttps://e.com/docs/\n";
+        let (out, err, _) = batch(input, "rewrite", &flags);
+
+        assert_eq!(err, "");
+        assert_eq!(out, "https://e.com/docs/?a=2\nhttps://e.com/docs/?a=2\n");
+        let flags: Vec<String> = ["--host", "s/a(/b/"].iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            rewrite_rules(&flags),
+            Err("Invalid --host: unclosed group at byte offset 3".to_string())
+        );
+    }
+
+    #[test]
+    fn cache_key_gives_equivalent_urls_one_key() {
+        let input = "HTTPS://Example.com:443/a/../b?utm_source=x&b=2&a=1#top\n\
+                     https://example.com/b?a=1&b=2\n\
+                     https://example.com/b?a=1&b=3\n";
+        let (out, _, _) = batch(input, "cache-key", &[]);
+        assert_eq!(
+            out,
+            "https://example.com/b?a=1&b=2\n\
+             https://example.com/b?a=1&b=2\n\
+             https://example.com/b?a=1&b=3\n"
+        );
+
+        let (out, _, _) = batch(input, "cache-key", &["--hash", "fnv", "--keep-param-order"]);
+        let keys: Vec<&str> = out.lines().collect();
+        assert_eq!(keys.len(), 3);
+        assert!(keys.iter().all(|key| key.len() == 16));
+        assert_ne!(keys[0], keys[1]);
+
+        let flags = vec!["--hash".to_string(), "md5".to_string()];
+        assert_eq!(
+            CliOptions::try_parse(&flags).err().as_deref(),
+            Some("Unknown --hash: md5. Use 'none', 'fnv', or 'sha256'")
+        );
+    }
+
+    #[test]
+    fn anonymize_tokens_repeat_within_a_key() {
+        let input = "/orders/42?user=alice&page=2\n\
+                     https://shop.example/orders/7?user=alice&page=3#top\n";
+        let flags = ["--key", "s3cret", "--keep-params", "page,lang", "--path-depth", "1"];
+        let (out, _, _) = batch(input, "anonymize", &flags);
+        assert_eq!(
+            out,
+            "/orders/982005f6?user=765542af&page=2\n\
+             https://shop.example/orders/f915ee12?user=765542af&page=3#220053c4\n"
+        );
+
+        let (other, _, _) = batch(input, "anonymize", &["--key", "other", "--hash-host"]);
+        assert!(other.starts_with("/orders/42?user=8244fe1a&page="));
+        assert!(!other.contains("shop.example"));
+        let flags = vec!["--path-depth".to_string(), "-1".to_string()];
+        assert_eq!(
+            CliOptions::try_parse(&flags).err().as_deref(),
+            Some("Invalid --path-depth: expected a non-negative number")
+        );
+    }
+
+    #[test]
+    fn similar_scores_a_pair_or_sorts_a_batch() {
+        let scores = |positionals: &[&str], flags: &[&str]| -> Vec<(String, String)> {
+            similar(&args(positionals), &args(flags))
+                .unwrap()
+                .into_iter()
+                .map(|(score, url)| (format!("{:.3}", score), url))
+                .collect()
+        };
+        let pair = scores(&["https://www.e.com/a/b/c?x=1&y=2", "https://e.com/a/b?y=3&z=1"], &[]);
+        assert_eq!(pair[0].0, "0.475");
+
+        let path = std::env::temp_dir().join(format!("url_encoder-similar-{}", process::id()));
+        std::fs::write(&path, "ftp://b.org/x\nhttps://e.com/a\nnot a url\nhttps://e.com/a/./b\n")
+            .unwrap();
+        let path = path.to_str().unwrap();
+        let expected = [
+            ("0.875", "https://e.com/a"),
+            ("0.833", "https://e.com/a/./b"),
+            ("0.000", "ftp://b.org/x"),
+            ("0.000", "not a url"),
+        ];
+        let batch = scores(&["https://e.com/a/b"], &["--batch", path]);
+        let expected: Vec<(String, String)> =
+            expected.iter().map(|(s, u)| (s.to_string(), u.to_string())).collect();
+        assert_eq!(batch, expected);
+        let normalized = scores(&["https://e.com/a/b"], &["--batch", path, "--normalize"]);
+        assert_eq!(normalized[0], ("1.000".to_string(), "https://e.com/a/./b".to_string()));
+        std::fs::remove_file(path).unwrap();
+
+        assert!(similar(&args(&["https://e.com/"]), &[]).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! C bindings, linked through the `jai-ffi` crate and declared in
+//! `include/jai.h`
+//!
+//! Strings cross the boundary as NUL-terminated UTF-8. Every `char *` a
+//! function returns is owned by the caller and must be released with
+//! `jai_free_string`; inputs stay owned by the caller and are only read.
+//! On failure a function returns NULL and stores one of the `JAI_ERROR_*`
+//! codes in `*error`; on success it stores `JAI_OK`. `error` may be NULL
+//! when the caller does not need the code.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+use crate::{DecodeError, DecodeOptions, URLEncoder};
+
+/// Success
+pub const JAI_OK: c_int = 0;
+/// The input pointer was NULL
+pub const JAI_ERROR_NULL_INPUT: c_int = 1;
+/// The input was not valid UTF-8
+pub const JAI_ERROR_INPUT_NOT_UTF8: c_int = 2;
+/// The result holds a NUL byte (`%00`) and cannot be a C string
+pub const JAI_ERROR_NUL_IN_OUTPUT: c_int = 3;
+/// `DecodeError::TruncatedEscape`
+pub const JAI_ERROR_TRUNCATED_ESCAPE: c_int = 10;
+/// `DecodeError::InvalidHex`
+pub const JAI_ERROR_INVALID_HEX: c_int = 11;
+/// `DecodeError::InvalidUtf8`
+pub const JAI_ERROR_INVALID_UTF8: c_int = 12;
+/// `DecodeError::InvalidBase64`
+pub const JAI_ERROR_INVALID_BASE64: c_int = 13;
+/// `DecodeError::InvalidPadding`
+pub const JAI_ERROR_INVALID_PADDING: c_int = 14;
+/// `DecodeError::TruncatedBase64`
+pub const JAI_ERROR_TRUNCATED_BASE64: c_int = 15;
+/// `DecodeError::OddHexLength`
+pub const JAI_ERROR_ODD_HEX_LENGTH: c_int = 16;
+
+/// The `JAI_ERROR_*` code of `e`
+fn decode_error_code(e: &DecodeError) -> c_int {
+    match e {
+        DecodeError::TruncatedEscape { .. } => JAI_ERROR_TRUNCATED_ESCAPE,
+        DecodeError::InvalidHex { .. } => JAI_ERROR_INVALID_HEX,
+        DecodeError::InvalidUtf8 { .. } => JAI_ERROR_INVALID_UTF8,
+        DecodeError::InvalidBase64 { .. } => JAI_ERROR_INVALID_BASE64,
+        DecodeError::InvalidPadding { .. } => JAI_ERROR_INVALID_PADDING,
+        DecodeError::TruncatedBase64 { .. } => JAI_ERROR_TRUNCATED_BASE64,
+        DecodeError::OddHexLength { .. } => JAI_ERROR_ODD_HEX_LENGTH,
+    }
+}
+
+/// Store `code` in `*error` unless `error` is NULL
+///
+/// # Safety
+///
+/// `error` must be NULL or valid for writes.
+unsafe fn set_error(error: *mut c_int, code: c_int) {
+    if !error.is_null() {
+        *error = code;
+    }
+}
+
+/// `input` as a `&str`, or the code to report
+///
+/// # Safety
+///
+/// `input` must be NULL or point to a NUL-terminated string that lives for
+/// `'a`.
+unsafe fn input_str<'a>(input: *const c_char) -> Result<&'a str, c_int> {
+    if input.is_null() {
+        return Err(JAI_ERROR_NULL_INPUT);
+    }
+    CStr::from_ptr(input).to_str().map_err(|_| JAI_ERROR_INPUT_NOT_UTF8)
+}
+
+/// Hand `result` to C: an owned string and `JAI_OK`, or NULL and the code
+///
+/// # Safety
+///
+/// `error` must be NULL or valid for writes.
+unsafe fn into_c(result: Result<String, c_int>, error: *mut c_int) -> *mut c_char {
+    match result.and_then(|text| CString::new(text).map_err(|_| JAI_ERROR_NUL_IN_OUTPUT)) {
+        Ok(text) => {
+            set_error(error, JAI_OK);
+            text.into_raw()
+        }
+        Err(code) => {
+            set_error(error, code);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// `URLEncoder::encode` of `input`
+///
+/// # Safety
+///
+/// `input` must be NULL or a NUL-terminated string, and `error` NULL or
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn jai_encode(input: *const c_char, error: *mut c_int) -> *mut c_char {
+    let result = input_str(input).map(|input| URLEncoder::new(input.to_string()).encode());
+    into_c(result, error)
+}
+
+/// `URLEncoder::decode_with` of `input`, passing malformed escapes through
+/// when `lenient` is nonzero
+///
+/// On a decoding error `*error_offset` gets the byte offset the error
+/// points at, as in `DecodeError::offset`; `error_offset` may be NULL.
+///
+/// # Safety
+///
+/// `input` must be NULL or a NUL-terminated string, and `error` and
+/// `error_offset` NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn jai_decode(
+    input: *const c_char,
+    lenient: c_int,
+    error: *mut c_int,
+    error_offset: *mut usize,
+) -> *mut c_char {
+    let options = DecodeOptions { lenient: lenient != 0, ..DecodeOptions::default() };
+    let result = input_str(input).and_then(|input| {
+        URLEncoder::new(input.to_string()).decode_with(&options).map_err(|e| {
+            if !error_offset.is_null() {
+                *error_offset = e.offset();
+            }
+            decode_error_code(&e)
+        })
+    });
+    into_c(result, error)
+}
+
+/// `URLEncoder::analyze_json` of `input`: the object `analyze --json`
+/// prints
+///
+/// # Safety
+///
+/// `input` must be NULL or a NUL-terminated string, and `error` NULL or
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn jai_analyze_json(
+    input: *const c_char,
+    error: *mut c_int,
+) -> *mut c_char {
+    let result = input_str(input).map(|input| URLEncoder::new(input.to_string()).analyze_json());
+    into_c(result, error)
+}
+
+/// Release a string returned by any `jai_*` function; NULL is ignored
+///
+/// # Safety
+///
+/// `text` must be NULL or a pointer a `jai_*` function returned that has
+/// not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn jai_free_string(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Call a `jai_*` function on `input`, taking ownership of the result
+    fn call(
+        f: impl Fn(*const c_char, *mut c_int) -> *mut c_char,
+        input: &[u8],
+    ) -> (Option<String>, c_int) {
+        let input = CString::new(input).unwrap();
+        let mut error = -1;
+        let output = f(input.as_ptr(), &mut error);
+        if output.is_null() {
+            return (None, error);
+        }
+        let text = unsafe { CStr::from_ptr(output) }.to_str().unwrap().to_string();
+        unsafe { jai_free_string(output) };
+        (Some(text), error)
+    }
+
+    fn decode(input: &str, lenient: c_int) -> (Option<String>, c_int, usize) {
+        let mut offset = usize::MAX;
+        let offset_ptr: *mut usize = &mut offset;
+        let (text, error) = call(
+            |input, error| unsafe { jai_decode(input, lenient, error, offset_ptr) },
+            input.as_bytes(),
+        );
+        (text, error, offset)
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let encode = |input: *const c_char, error| unsafe { jai_encode(input, error) };
+        let (encoded, error) = call(encode, "café & crème".as_bytes());
+
+        assert_eq!((encoded.as_deref(), error), (Some("caf%C3%A9%20%26%20cr%C3%A8me"), JAI_OK));
+        let decoded = decode(&encoded.unwrap(), 0);
+        assert_eq!(decoded, (Some("café & crème".to_string()), JAI_OK, usize::MAX));
+    }
+
+    #[test]
+    fn decode_reports_the_variant_and_offset() {
+        assert_eq!(decode("100%zz", 0), (None, JAI_ERROR_INVALID_HEX, 3));
+        assert_eq!(decode("a%4", 0), (None, JAI_ERROR_TRUNCATED_ESCAPE, 1));
+        assert_eq!(decode("ok%ff", 0), (None, JAI_ERROR_INVALID_UTF8, 2));
+        assert_eq!(decode("100%zz", 1), (Some("100%zz".to_string()), JAI_OK, usize::MAX));
+        assert_eq!(decode("a%00b", 0), (None, JAI_ERROR_NUL_IN_OUTPUT, usize::MAX));
+    }
+
+    #[test]
+    fn bad_input_pointers_are_reported() {
+        let encode = |input: *const c_char, error| unsafe { jai_encode(input, error) };
+        let mut error = -1;
+
+        assert!(unsafe { jai_analyze_json(ptr::null(), &mut error) }.is_null());
+        assert_eq!(error, JAI_ERROR_NULL_INPUT);
+        assert_eq!(call(encode, b"\xff"), (None, JAI_ERROR_INPUT_NOT_UTF8));
+        assert!(unsafe { jai_encode(ptr::null(), ptr::null_mut()) }.is_null());
+        unsafe { jai_free_string(ptr::null_mut()) };
+    }
+
+    #[test]
+    fn header_codes_match() {
+        let codes = [
+            ("JAI_OK", JAI_OK),
+            ("JAI_ERROR_NULL_INPUT", JAI_ERROR_NULL_INPUT),
+            ("JAI_ERROR_INPUT_NOT_UTF8", JAI_ERROR_INPUT_NOT_UTF8),
+            ("JAI_ERROR_NUL_IN_OUTPUT", JAI_ERROR_NUL_IN_OUTPUT),
+            ("JAI_ERROR_TRUNCATED_ESCAPE", JAI_ERROR_TRUNCATED_ESCAPE),
+            ("JAI_ERROR_INVALID_HEX", JAI_ERROR_INVALID_HEX),
+            ("JAI_ERROR_INVALID_UTF8", JAI_ERROR_INVALID_UTF8),
+            ("JAI_ERROR_INVALID_BASE64", JAI_ERROR_INVALID_BASE64),
+            ("JAI_ERROR_INVALID_PADDING", JAI_ERROR_INVALID_PADDING),
+            ("JAI_ERROR_TRUNCATED_BASE64", JAI_ERROR_TRUNCATED_BASE64),
+            ("JAI_ERROR_ODD_HEX_LENGTH", JAI_ERROR_ODD_HEX_LENGTH),
+        ];
+        let header = include_str!("../include/jai.h");
+        let defines: Vec<&str> = header
+            .lines()
+            .filter(|line| line.starts_with("#define JAI_") && line.split(' ').count() == 3)
+            .collect();
+
+        assert_eq!(defines.len(), codes.len());
+        for (name, code) in codes {
+            assert!(defines.contains(&format!("#define {} {}", name, code).as_str()), "{}", name);
+        }
+    }
+}
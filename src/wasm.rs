@@ -0,0 +1,99 @@
+//! JavaScript bindings, built into a package by `wasm-pack build wasm`
+//! (the `jai-wasm` crate)
+//!
+//! Every binding takes strings and returns a string, JSON where the result
+//! is structured. Failures are thrown as JS `Error`s named `URLEncoderError`
+//! with a `kind` (the Rust variant name, such as `InvalidHex`) and an
+//! `offset`: the UTF-8 byte offset the error points at, or `null` for
+//! errors without one.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{DecodeError, ParseError, URLEncoder};
+
+/// A JS `Error` carrying `kind` and `offset` properties
+fn js_error(kind: &str, offset: Option<usize>, message: &str) -> JsValue {
+    let error = js_sys::Error::new(message);
+    error.set_name("URLEncoderError");
+    let offset = offset.map_or(JsValue::NULL, |offset| JsValue::from(offset as f64));
+    for (key, value) in [("kind", JsValue::from_str(kind)), ("offset", offset)] {
+        js_sys::Reflect::set(&error, &JsValue::from_str(key), &value)
+            .expect("a new Error accepts properties");
+    }
+    error.into()
+}
+
+fn decode_error(e: DecodeError) -> JsValue {
+    js_error(e.kind(), Some(e.offset()), &e.to_string())
+}
+
+fn parse_error(e: ParseError) -> JsValue {
+    js_error(e.kind(), None, &e.to_string())
+}
+
+/// `pairs` as a JSON array of `[key, value]` arrays, a missing value being
+/// `null`
+fn pairs_json(pairs: &[(String, Option<String>)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(key, value)| {
+            format!("[{},{}]", crate::json_string(Some(key)), crate::json_string(value.as_deref()))
+        })
+        .collect();
+    format!("[{}]", pairs.join(","))
+}
+
+/// `URLEncoder::encode`
+#[wasm_bindgen]
+pub fn encode(input: &str) -> String {
+    URLEncoder::new(input.to_string()).encode()
+}
+
+/// `URLEncoder::decode`; throws on a malformed escape or invalid UTF-8
+#[wasm_bindgen]
+pub fn decode(input: &str) -> Result<String, JsValue> {
+    URLEncoder::new(input.to_string()).decode().map_err(decode_error)
+}
+
+/// `URLEncoder::normalize`; throws if the input does not parse
+#[wasm_bindgen]
+pub fn normalize(input: &str) -> Result<String, JsValue> {
+    URLEncoder::new(input.to_string()).normalize().map_err(parse_error)
+}
+
+/// `URLEncoder::parse_query` as JSON: `[["a","1"],["flag",null]]`
+#[wasm_bindgen(js_name = parseQuery)]
+pub fn parse_query(input: &str) -> String {
+    pairs_json(&URLEncoder::new(input.to_string()).parse_query())
+}
+
+/// `URLEncoder::analyze_json`, the object `analyze --json` prints
+#[wasm_bindgen(js_name = analyzeJson)]
+pub fn analyze_json(input: &str) -> String {
+    URLEncoder::new(input.to_string()).analyze_json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_json_keeps_order_duplicates_and_missing_values() {
+        let pairs = URLEncoder::new("?a=1&flag&a=x%22y&b=".to_string()).parse_query();
+
+        assert_eq!(pairs_json(&pairs), r#"[["a","1"],["flag",null],["a","x\"y"],["b",""]]"#);
+        assert_eq!(pairs_json(&[]), "[]");
+    }
+
+    #[test]
+    fn error_kinds_name_the_variant() {
+        let decode = |input: &str| URLEncoder::new(input.to_string()).decode().unwrap_err();
+        let normalize = |input: &str| URLEncoder::new(input.to_string()).normalize().unwrap_err();
+
+        assert_eq!(decode("a%zz").kind(), "InvalidHex");
+        assert_eq!(decode("a%").kind(), "TruncatedEscape");
+        assert_eq!(decode("%ff").kind(), "InvalidUtf8");
+        assert_eq!(normalize("no scheme").kind(), "MissingScheme");
+        assert_eq!(normalize("http://e.com:99999/").kind(), "InvalidPort");
+    }
+}
@@ -0,0 +1,285 @@
+//! End-to-end tests of the url_encoder binary: exit statuses, help,
+//! traces and the repl, run as a separate process
+
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+
+// The exit statuses documented under "Exit status:" in --help
+const USAGE_EXIT_CODE: i32 = 1;
+const PARSE_EXIT_CODE: i32 = 2;
+const FAILED_CHECK_EXIT_CODE: i32 = 3;
+const PARTIAL_EXIT_CODE: i32 = 4;
+
+fn output_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("url_encoder-cli-{}-{}", name, process::id()))
+}
+
+/// Exit status and stdout of the binary run with `args` and `stdin`
+fn run_cli(args: &[&str], stdin: &str) -> (i32, String) {
+    let (code, stdout, _) = run_cli_stderr(args, stdin);
+    (code, stdout)
+}
+
+/// `run_cli`, with stderr too
+fn run_cli_stderr(args: &[&str], stdin: &str) -> (i32, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_url_encoder"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn cli_exit_codes_for_success_usage_and_parse_errors() {
+    assert_eq!(run_cli(&["a b", "encode"], ""), (0, "Encoded: a%20b\n".to_string()));
+    let (code, help) = run_cli(&["--help"], "");
+    assert_eq!(code, 0);
+    assert!(help.contains("\nUsage: url_encoder") && help.contains("\nExit status:\n"));
+
+    assert_eq!(run_cli(&["x", "frobnicate"], "").0, USAGE_EXIT_CODE);
+    assert_eq!(run_cli(&["x", "rewrite", "--on-conflict", "never"], "").0, USAGE_EXIT_CODE);
+    assert_eq!(run_cli(&["--file", "/nonexistent/urls.txt", "decode"], "").0, USAGE_EXIT_CODE);
+
+    assert_eq!(run_cli(&["%zz", "decode"], ""), (PARSE_EXIT_CODE, String::new()));
+    assert_eq!(run_cli(&["decode", "--raw"], "ok%zz").0, PARSE_EXIT_CODE);
+    assert_eq!(run_cli(&["no scheme", "normalize"], "").0, PARSE_EXIT_CODE);
+}
+
+#[test]
+fn cli_exit_codes_for_failed_checks_and_batches() {
+    assert_eq!(run_cli(&["https://e.com/", "validate"], "").0, 0);
+    assert_eq!(run_cli(&["not a url", "validate"], "").0, FAILED_CHECK_EXIT_CODE);
+    assert_eq!(run_cli(&["mailto:a@b.com", "validate"], ""), (0, "Valid\n".to_string()));
+    let risky = ["javascript:alert(1)", "analyze", "--fail-on-risk"];
+    assert_eq!(run_cli(&risky, "").0, FAILED_CHECK_EXIT_CODE);
+    let long = ["https://e.com/aaaa", "analyze", "--max-length", "5", "--fail-on-limit"];
+    assert_eq!(run_cli(&long, "").0, FAILED_CHECK_EXIT_CODE);
+
+    assert_eq!(run_cli(&["decode"], "a%20b\nc\n"), (0, "a b\nc\n".to_string()));
+    assert_eq!(run_cli(&["decode"], "a%20b\n%zz\nc\n"), (PARTIAL_EXIT_CODE, "a b\nc\n".into()));
+}
+
+#[test]
+fn cli_quiet_keeps_only_the_exit_code() {
+    assert_eq!(run_cli(&["https://e.com/", "validate", "-q"], ""), (0, String::new()));
+    let code = run_cli(&["not a url", "validate", "--quiet"], "");
+    assert_eq!(code, (FAILED_CHECK_EXIT_CODE, String::new()));
+    assert_eq!(run_cli(&["-q", "decode"], "a\n%zz\n"), (PARTIAL_EXIT_CODE, String::new()));
+
+    let path = output_path("quiet.txt");
+    let args = ["a b", "encode", "-q", "-o", path.to_str().unwrap()];
+    assert_eq!(run_cli(&args, ""), (0, String::new()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Encoded: a%20b\n");
+}
+
+#[test]
+fn cli_usage_errors_explain_themselves() {
+    let error = |args: &[&str]| {
+        let (code, stdout, stderr) = run_cli_stderr(args, "");
+        assert_eq!((code, stdout.as_str()), (USAGE_EXIT_CODE, ""), "{:?}", args);
+        stderr
+    };
+
+    let typo = "Unknown flag: --jsno. Did you mean --json?\n";
+    assert_eq!(error(&["a", "encode", "--jsno"]), typo);
+    assert_eq!(error(&["equiv", "a", "b", "--by"]), "--by needs a value\n");
+    assert_eq!(
+        error(&["join", "http://a/", "b", "--ignore-fragment"]),
+        "Unknown flag: --ignore-fragment. See --help for the flags\n"
+    );
+    assert_eq!(error(&["decode", "--file"]), "--file needs a value\n");
+    let extra = error(&["--batch", "urls.txt", "decode", "extra"]);
+    assert_eq!(extra, "Unexpected argument: extra\n");
+    let anonymize = error(&["/a?b=c", "anonymize"]);
+    assert_eq!(anonymize, "anonymize needs --key: the secret its tokens are keyed with\n");
+    assert_eq!(
+        error(&["x", "encode", "--fail-on-risk"]),
+        "encode does not take --fail-on-risk. See 'url_encoder encode --help' for its flags\n"
+    );
+    let suffixes = error(&["https://e.com/", "analyze", "--suffix-list", "/nonexistent/psl.dat"]);
+    assert!(suffixes.starts_with("cannot read /nonexistent/psl.dat: "), "{}", suffixes);
+    assert_eq!(suffixes.lines().count(), 1);
+    let group = error(&["%41", "decode", "--group"]);
+    assert!(group.starts_with("decode does not take --group."), "{}", group);
+    let operation = error(&["x", "decod"]);
+    assert!(operation.starts_with("Unknown operation: decod. Did you mean decode?"));
+}
+
+#[test]
+fn cli_help_version_and_completions() {
+    let (code, help) = run_cli(&["decode", "--help"], "");
+    assert_eq!(code, 0);
+    assert!(help.starts_with("url_encoder decode: Decode percent-escapes\n\n"));
+    let (code, help) = run_cli(&["x", "equiv", "-h"], "");
+    assert_eq!(code, 0);
+    assert!(help.starts_with("url_encoder equiv: "));
+    let (code, version) = run_cli(&["--version"], "");
+    assert_eq!(code, 0);
+    let expected = format!("url_encoder {}\nfeatures: ", env!("CARGO_PKG_VERSION"));
+    assert!(version.starts_with(&expected), "{}", version);
+
+    let (code, script) = run_cli(&["completions", "fish"], "");
+    assert_eq!(code, 0);
+    assert!(script.starts_with("# fish completion for url_encoder"), "{}", script);
+    let (code, _, err) = run_cli_stderr(&["completions", "tcsh"], "");
+    assert_eq!(code, 1);
+    assert_eq!(err, "Error: Unknown shell: tcsh. Use 'bash', 'zsh', or 'fish'\n");
+}
+#[test]
+fn verbose_traces_the_split_and_each_normalization_rule() {
+    let url = "HTTPS://u@Example.COM:443/a/./b/../%7ec?b=%7e&a=1";
+    let args = [url, "normalize", "-vv", "--sort-params"];
+    let (code, stdout, stderr) = run_cli_stderr(&args, "");
+    assert_eq!((code, stdout.as_str()), (0, "https://u@example.com/a/~c?a=1&b=~\n"));
+    let expected = [
+        concat!(
+            r#"trace: parse: scheme "HTTPS", userinfo "u", host "Example.COM", port "443", "#,
+            r#"path "/a/./b/../%7ec", query "b=%7e&a=1""#,
+        ),
+        r#"trace: normalize: lowercase scheme: "HTTPS" -> "https""#,
+        r#"trace: normalize: host: "Example.COM" -> "example.com""#,
+        "trace: normalize: drop default port Some(443)",
+        r#"trace: normalize: dot segments: "/a/./b/../%7ec" -> "/a/%7ec""#,
+        r#"trace: normalize: escapes in path: "/a/%7ec" -> "/a/~c""#,
+        r#"trace: normalize: escapes in query: "b=%7e&a=1" -> "b=~&a=1""#,
+        r#"trace: normalize: sort query: Some("b=~&a=1") -> Some("a=1&b=~")"#,
+    ];
+    assert_eq!(stderr.lines().collect::<Vec<_>>(), expected);
+
+    let (_, _, stderr) = run_cli_stderr(&["https://e.com/", "normalize", "-v"], "");
+    assert_eq!(stderr, "trace: parse: scheme \"https\", host \"e.com\", path \"/\"\n");
+}
+
+#[test]
+fn verbose_traces_escapes_and_decode_passes() {
+    let (_, stdout, stderr) = run_cli_stderr(&["a b/é", "encode", "-vv"], "");
+    assert_eq!(stdout, "Encoded: a%20b%2F%C3%A9\n");
+    let expected = [
+        "trace: encode: escape ' ' at byte 1 as %20: space",
+        "trace: encode: escape '/' at byte 3 as %2F: reserved delimiter",
+        "trace: encode: escape 0xC3 at byte 4 as %C3: non-ASCII byte",
+        "trace: encode: escape 0xA9 at byte 5 as %A9: non-ASCII byte",
+        "trace: encode: escaped 4 of 6 bytes",
+    ];
+    assert_eq!(stderr.lines().collect::<Vec<_>>(), expected);
+    let (_, _, stderr) = run_cli_stderr(&["a b", "encode", "--form", "-v"], "");
+    assert_eq!(stderr, "trace: encode: escaped 1 of 3 bytes\n");
+
+    let (_, _, stderr) = run_cli_stderr(&["a%252520b", "decode", "--recursive", "-v"], "");
+    let expected = [
+        r#"trace: decode: pass 1: "a%252520b" -> "a%2520b""#,
+        r#"trace: decode: pass 2: "a%2520b" -> "a%20b""#,
+        r#"trace: decode: pass 3: "a%20b" -> "a b""#,
+        "trace: decode: pass 4 changes nothing, stopping",
+    ];
+    assert_eq!(stderr.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn quiet_silences_traces() {
+    let url = "HTTPS://Example.COM:443/a/../b";
+    assert_eq!(run_cli_stderr(&[url, "normalize", "-vv", "-q"], ""), (0, "".into(), "".into()));
+    let args = ["a%2520b", "decode", "--recursive", "--verbose", "--quiet"];
+    assert_eq!(run_cli_stderr(&args, ""), (0, "".into(), "".into()));
+    assert_eq!(run_cli_stderr(&["-q", "-vv", "encode"], "a b\nc\n").2, "");
+}
+
+#[test]
+fn cli_parses_flags_before_positionals_and_after_the_terminator() {
+    assert_eq!(run_cli(&["--lower-hex", "encode", "a/b"], ""), (0, "Encoded: a%2fb\n".into()));
+    assert_eq!(run_cli(&["--form", "-q", "a b", "encode"], ""), (0, String::new()));
+    assert_eq!(run_cli(&["decode", "--", "-v%20x"], ""), (0, "Decoded: -v x\n".into()));
+    assert_eq!(run_cli(&["--", "--json", "encode"], ""), (0, "Encoded: --json\n".into()));
+    let equiv = ["-q", "equiv", "http://a/", "HTTP://A:80"];
+    assert_eq!(run_cli(&equiv, ""), (0, String::new()));
+}
+
+#[test]
+fn cli_repl_survives_errors_and_exits_at_end_of_input() {
+    let script = ":mode decode\n%zz\n:mode bogus\nok%21\n";
+    let (code, out, err) = run_cli_stderr(&["repl"], script);
+    assert_eq!((code, out.as_str()), (0, "Decoded: ok!\n"));
+    assert!(err.contains("%zz"), "{}", err);
+    assert!(err.contains("Error: Unknown operation: bogus."), "{}", err);
+
+    let (code, out, err) = run_cli_stderr(&["repl", "-v", "--form"], "a b\n:reset\na b\n");
+    assert_eq!((code, out.as_str()), (0, "Encoded: a+b\nEncoded: a%20b\n"));
+    assert_eq!(err.matches("trace: encode").count(), 2, "{}", err);
+    let (code, _, err) = run_cli_stderr(&["repl", "--batch", "urls.txt"], "");
+    assert_eq!((code, err.as_str()), (1, "Error: --batch is not available in the repl\n"));
+}
+
+#[test]
+fn cli_check_exits_3_on_a_round_trip_mismatch() {
+    let ok = "encode then decode: ok\ndecode then encode: ok\n";
+    assert_eq!(run_cli(&["check", "caf%C3%A9%20x"], ""), (0, ok.to_string()));
+    assert_eq!(run_cli(&["check", "--form", "a+b"], ""), (0, ok.to_string()));
+
+    let (code, out) = run_cli(&["check", "a%2f"], "");
+    assert_eq!(code, FAILED_CHECK_EXIT_CODE);
+    assert_eq!(out, "encode then decode: ok\ndecode then encode: differs: a%2f -> a%2F\n");
+    assert_eq!(run_cli(&["check", "--lower-hex", "a%2f"], "").0, 0);
+    assert_eq!(run_cli(&["check", "-q", "a+b"], ""), (FAILED_CHECK_EXIT_CODE, String::new()));
+}
+
+#[test]
+fn cli_bench_reports_without_printing_results() {
+    let corpus = output_path("bench");
+    std::fs::write(&corpus, "https://example.com/a b\nhttps://e.com/?q=é\n").unwrap();
+    let corpus_arg = corpus.display().to_string();
+
+    let args = ["bench", "--file", &corpus_arg, "--iterations", "2", "--json"];
+    let (code, json) = run_cli(&args, "");
+    assert_eq!(code, 0);
+    assert!(json.starts_with("{\"operation\":\"encode\",\"urls\":2,"), "{}", json);
+    assert!(!json.contains("example.com") && !json.contains("%20"));
+    let (code, text) = run_cli(&["bench", "--file", &corpus_arg, "--op", "analyze"], "");
+    assert_eq!(code, 0);
+    assert!(text.starts_with("bench analyze: 2 URLs (42 bytes), 10 iterations after 1 warm-up\n"));
+    assert_eq!(run_cli(&["bench", "--op", "encode"], "").0, USAGE_EXIT_CODE);
+    std::fs::remove_file(&corpus).unwrap();
+}
+
+#[test]
+fn cli_mmap_batch_matches_buffered_batch() {
+    let path = output_path("mmap");
+    let mut input = String::new();
+    for i in 0..60_000 {
+        let end = if i % 3 == 0 { "\r\n" } else { "\n" };
+        match i % 1000 {
+            0 => input.push_str("# section\n"),
+            500 => input.push_str(&format!("broken%zz{}", end)),
+            _ => input.push_str(&format!("https://e.com/p%20{}?q=caf%C3%A9&n={}{}", i, i * 7, end)),
+        }
+    }
+    input.push_str("https://e.com/last%2Fline");
+    std::fs::write(&path, &input).unwrap();
+    let path_arg = path.display().to_string();
+    assert!(input.len() > 2 * 1024 * 1024);
+
+    for flags in [&["decode"][..], &["decode", "--jobs", "3"], &["encode", "--form"]] {
+        let mut args = vec!["--batch", &path_arg];
+        args.extend(flags);
+        let buffered = run_cli_stderr(&args, "");
+        args.push("--mmap");
+        let mapped = run_cli_stderr(&args, "");
+        assert!(mapped == buffered, "{:?}", flags);
+    }
+    let (code, out, err) = run_cli_stderr(&["--batch", &path_arg, "decode", "--mmap"], "");
+    assert_eq!(code, PARTIAL_EXIT_CODE);
+    assert!(out.starts_with("https://e.com/p 1?q=café&n=7\n"));
+    assert!(out.ends_with("https://e.com/last/line\n"));
+    assert!(err.starts_with("line 501: Error: "), "{}", err);
+    std::fs::remove_file(&path).unwrap();
+}
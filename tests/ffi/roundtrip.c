@@ -0,0 +1,81 @@
+/*
+ * C harness for the jai FFI layer, compiled and run by ffi/tests/c.rs.
+ * Exits nonzero and names the failing check when something is off.
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "jai.h"
+
+static int failures = 0;
+
+static void check(int ok, const char *what) {
+    if (!ok) {
+        fprintf(stderr, "FAILED: %s\n", what);
+        failures++;
+    }
+}
+
+/* Decode input and check the result, code and offset; expected NULL means
+ * decoding must fail */
+static void check_decode(const char *input, int lenient, const char *expected, int code,
+                         size_t offset) {
+    int error = -1;
+    size_t error_offset = (size_t)-1;
+    char *decoded = jai_decode(input, lenient, &error, &error_offset);
+
+    check(error == code, input);
+    if (expected == NULL) {
+        check(decoded == NULL, input);
+        check(error_offset == offset, input);
+    } else {
+        check(decoded != NULL && strcmp(decoded, expected) == 0, input);
+        check(error_offset == (size_t)-1, input);
+    }
+    jai_free_string(decoded);
+}
+
+int main(void) {
+    const char *inputs[] = {
+        "café & crème", "a/b?c=d#e", "", "100% pure", "日本語 + emoji \xf0\x9f\x98\x80",
+    };
+    int error = -1;
+
+    for (size_t i = 0; i < sizeof inputs / sizeof inputs[0]; i++) {
+        char *encoded = jai_encode(inputs[i], &error);
+        check(encoded != NULL && error == JAI_OK, "encode");
+        char *decoded = jai_decode(encoded, 0, &error, NULL);
+        check(decoded != NULL && error == JAI_OK, "decode");
+        check(decoded != NULL && strcmp(decoded, inputs[i]) == 0, inputs[i]);
+        jai_free_string(encoded);
+        jai_free_string(decoded);
+    }
+
+    char *encoded = jai_encode("café & crème", NULL);
+    check(strcmp(encoded, "caf%C3%A9%20%26%20cr%C3%A8me") == 0, "encode output");
+    jai_free_string(encoded);
+
+    check_decode("100%zz", 0, NULL, JAI_ERROR_INVALID_HEX, 3);
+    check_decode("a%4", 0, NULL, JAI_ERROR_TRUNCATED_ESCAPE, 1);
+    check_decode("ok%ff", 0, NULL, JAI_ERROR_INVALID_UTF8, 2);
+    check_decode("100%zz", 1, "100%zz", JAI_OK, 0);
+    check_decode("50% off%", 1, "50% off%", JAI_OK, 0);
+    check_decode("a+b%20c", 0, "a b c", JAI_OK, 0);
+    check_decode("a%00b", 0, NULL, JAI_ERROR_NUL_IN_OUTPUT, (size_t)-1);
+
+    check(jai_decode(NULL, 0, &error, NULL) == NULL && error == JAI_ERROR_NULL_INPUT, "NULL input");
+    check(jai_encode("\xff", &error) == NULL && error == JAI_ERROR_INPUT_NOT_UTF8, "non-UTF-8");
+
+    char *json = jai_analyze_json("https://user@example.com/a%2520b?x=1", &error);
+    check(json != NULL && error == JAI_OK, "analyze");
+    check(json != NULL && strncmp(json, "{\"total_length\":36,", 19) == 0, "analyze JSON");
+    check(json != NULL && strstr(json, "\"credentials\":true") != NULL, "analyze credentials");
+    jai_free_string(json);
+    jai_free_string(NULL);
+
+    if (failures == 0) {
+        printf("ok\n");
+    }
+    return failures == 0 ? 0 : 1;
+}
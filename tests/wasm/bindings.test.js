@@ -0,0 +1,83 @@
+// Node harness for the wasm bindings. Build them first:
+//
+//     wasm-pack build wasm --target nodejs
+//     cargo build --bin url_encoder
+//     node --test tests/wasm/
+//
+// JAI_PKG and URL_ENCODER override where the package and the CLI are found.
+
+const assert = require("node:assert/strict");
+const { execFileSync } = require("node:child_process");
+const path = require("node:path");
+const test = require("node:test");
+
+const root = path.join(__dirname, "..", "..");
+const jai = require(process.env.JAI_PKG || path.join(root, "wasm", "pkg"));
+const cli = process.env.URL_ENCODER || path.join(root, "target", "debug", "url_encoder");
+
+function thrown(f) {
+  try {
+    f();
+  } catch (error) {
+    return error;
+  }
+  assert.fail("expected an exception");
+}
+
+test("encode percent-encodes UTF-8", () => {
+  assert.equal(jai.encode("café & crème"), "caf%C3%A9%20%26%20cr%C3%A8me");
+  assert.equal(jai.encode(""), "");
+});
+
+test("decode reverses encode", () => {
+  assert.equal(jai.decode("caf%C3%A9%20%26%20cr%C3%A8me"), "café & crème");
+  assert.equal(jai.decode("a+b"), "a b");
+});
+
+test("decode throws the error kind and byte offset", () => {
+  const error = thrown(() => jai.decode("100%zz"));
+  assert.ok(error instanceof Error);
+  assert.equal(error.name, "URLEncoderError");
+  assert.equal(error.kind, "InvalidHex");
+  assert.equal(error.offset, 3);
+  assert.match(error.message, /%zz at byte offset 3/);
+
+  assert.equal(thrown(() => jai.decode("é%")).offset, 2);
+  assert.equal(thrown(() => jai.decode("%ff")).kind, "InvalidUtf8");
+});
+
+test("normalize matches the normalize operation", () => {
+  assert.equal(jai.normalize("HTTP://EXAMPLE.com:80"), "http://example.com/");
+  assert.equal(jai.normalize("http://e.com/a/b/../c/%7e"), "http://e.com/a/c/~");
+  for (const input of ["https://Example.COM:443/A/./B?Q=Up#Frag", "http://e.com/%c3%a9?q=%2f"]) {
+    const expected = execFileSync(cli, [input, "normalize"], { encoding: "utf8" });
+    assert.equal(jai.normalize(input), expected.trimEnd());
+  }
+
+  const error = thrown(() => jai.normalize("no scheme"));
+  assert.equal(error.kind, "MissingScheme");
+  assert.equal(error.offset, null);
+});
+
+test("parseQuery returns ordered [key, value] pairs", () => {
+  assert.deepEqual(JSON.parse(jai.parseQuery("https://e.com/?a=1&flag&a=x+y&b=")), [
+    ["a", "1"],
+    ["flag", null],
+    ["a", "x y"],
+    ["b", ""],
+  ]);
+  assert.deepEqual(JSON.parse(jai.parseQuery("")), []);
+});
+
+test("analyzeJson prints what analyze --json does", () => {
+  const inputs = [
+    "https://user@example.com/a%2520b?x=1",
+    "http://xn--80ak6aa92e.com/login?next=https://evil.example/",
+    "not a url",
+  ];
+  for (const input of inputs) {
+    const expected = execFileSync(cli, [input, "analyze", "--json"], { encoding: "utf8" });
+    assert.equal(jai.analyzeJson(input), expected.trimEnd());
+    assert.equal(typeof JSON.parse(jai.analyzeJson(input)), "object");
+  }
+});
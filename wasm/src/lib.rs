@@ -0,0 +1,8 @@
+//! The `jai` JavaScript bindings as a `cdylib` for `wasm-pack`
+//!
+//! The bindings live in `jai::wasm`; this crate only gives them the crate
+//! type wasm-pack needs, so `jai` itself stays an `rlib` that also builds
+//! without `std`. Build with `wasm-pack build wasm --target nodejs` (or
+//! `--target web`).
+
+pub use jai::wasm::*;